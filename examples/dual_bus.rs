@@ -0,0 +1,112 @@
+//! Two independent slaves on the TWI0 and TWI1 of the atmega328pb, each with
+//! its own address and interrupt. Whatever the master writes to one of them
+//! is read back from the same one.
+#![cfg_attr(target_arch = "avr", no_std)]
+#![cfg_attr(target_arch = "avr", no_main)]
+#![cfg_attr(target_arch = "avr", feature(abi_avr_interrupt))]
+
+#[cfg(target_arch = "avr")]
+use core::cell::RefCell;
+
+#[cfg(target_arch = "avr")]
+use avr_device::interrupt::{self, Mutex};
+#[cfg(target_arch = "avr")]
+use avr_i2c_slave::{
+    bind_twi_interrupt,
+    irq_slave::IrqSlave,
+    twi::{AvrTwi, TWI1},
+    I2cSlave, Transfer, Twi,
+};
+#[cfg(target_arch = "avr")]
+use panic_halt as _;
+
+#[cfg(target_arch = "avr")]
+const BUFFER: usize = 8;
+
+#[cfg(target_arch = "avr")]
+static SLAVE0: Mutex<RefCell<Option<IrqSlave<AvrTwi, BUFFER>>>> = Mutex::new(RefCell::new(None));
+#[cfg(target_arch = "avr")]
+static SLAVE1: Mutex<RefCell<Option<IrqSlave<AvrTwi<TWI1>, BUFFER>>>> =
+    Mutex::new(RefCell::new(None));
+
+// Each TWI has its own interrupt handler running its slave
+#[cfg(target_arch = "avr")]
+bind_twi_interrupt!(TWI0 => TWI0_INTERRUPT, || interrupt::free(|cs| {
+    if let Some(i2c_slave) = SLAVE0.borrow(cs).borrow_mut().as_mut() {
+        i2c_slave.on_interrupt();
+    }
+}));
+#[cfg(target_arch = "avr")]
+bind_twi_interrupt!(TWI1 => TWI1_INTERRUPT, || interrupt::free(|cs| {
+    if let Some(i2c_slave) = SLAVE1.borrow(cs).borrow_mut().as_mut() {
+        i2c_slave.on_interrupt();
+    }
+}));
+
+// Firmware only runs on the AVR, host builds exist for `cargo test`
+#[cfg(not(target_arch = "avr"))]
+fn main() {}
+
+#[cfg(target_arch = "avr")]
+#[avr_device::entry]
+fn main() -> ! {
+    let dp = atmega_hal::Peripherals::take().unwrap();
+    let pins = atmega_hal::pins!(dp);
+
+    // Disabling power reduction for both TWIs
+    dp.CPU.prr0.modify(|_, w| w.prtwi0().clear_bit());
+    dp.CPU.prr1.modify(|_, w| w.prtwi1().clear_bit());
+
+    // Using external pullup resistors, so pins configured as floating inputs
+    let twi0 = AvrTwi::new(
+        dp.TWI0,
+        pins.pc4.into_floating_input(),
+        pins.pc5.into_floating_input(),
+    );
+    let twi1 = AvrTwi::new(
+        dp.TWI1,
+        pins.pe0.into_floating_input(),
+        pins.pe1.into_floating_input(),
+    );
+
+    let mut slave0 = I2cSlave::new(twi0, 0x26, TWI0_INTERRUPT.bind().unwrap());
+    slave0.init(false);
+    let mut slave0 = IrqSlave::new(slave0);
+    slave0.listen();
+
+    let mut slave1 = I2cSlave::new(twi1, 0x27, TWI1_INTERRUPT.bind().unwrap());
+    slave1.init(false);
+    let mut slave1 = IrqSlave::new(slave1);
+    slave1.listen();
+
+    interrupt::free(|cs| {
+        SLAVE0.borrow(cs).replace(Some(slave0));
+        SLAVE1.borrow(cs).replace(Some(slave1));
+    });
+
+    // Enable global interrupt
+    unsafe { interrupt::enable() };
+
+    loop {
+        interrupt::free(|cs| {
+            if let Some(i2c_slave) = SLAVE0.borrow(cs).borrow_mut().as_mut() {
+                echo(i2c_slave);
+            }
+            if let Some(i2c_slave) = SLAVE1.borrow(cs).borrow_mut().as_mut() {
+                echo(i2c_slave);
+            }
+        });
+    }
+}
+
+/// Answer the next read with the bytes of the last write
+#[cfg(target_arch = "avr")]
+fn echo<T: Twi, const N: usize>(i2c_slave: &mut IrqSlave<T, N>) {
+    if let Some(Ok(Transfer::Received(_))) = i2c_slave.take_event() {
+        let mut response = [0; N];
+        let received = i2c_slave.received();
+        response[..received.len()].copy_from_slice(received);
+
+        i2c_slave.set_response(&response[..received.len()]);
+    }
+}
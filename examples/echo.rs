@@ -0,0 +1,153 @@
+//! Register file demo: the master writes inputs to registers 0..3 and reads
+//! them back multiplied by 10 from registers 4..7.
+#![cfg_attr(target_arch = "avr", no_std)]
+#![cfg_attr(target_arch = "avr", no_main)]
+#![cfg_attr(target_arch = "avr", feature(abi_avr_interrupt))]
+
+#[cfg(target_arch = "avr")]
+use core::cell::RefCell;
+
+#[cfg(target_arch = "avr")]
+use avr_device::interrupt::{self, Mutex};
+#[cfg(target_arch = "avr")]
+use avr_i2c_slave::{
+    bind_twi_interrupt, irq_slave::IrqSlave, register_map::RegisterMap, twi::AvrTwi, I2cSlave,
+    Transfer,
+};
+#[cfg(target_arch = "avr")]
+use panic_halt as _;
+#[cfg(target_arch = "avr")]
+use ufmt::{uwrite, uwriteln};
+
+// Registers 0..3 are written by the master, 4..7 hold them multiplied by 10
+#[cfg(target_arch = "avr")]
+const REGISTERS: usize = 8;
+#[cfg(target_arch = "avr")]
+const INPUTS: usize = 4;
+
+#[cfg(target_arch = "avr")]
+static I2C_SLAVE: Mutex<RefCell<Option<IrqSlave<AvrTwi, REGISTERS>>>> =
+    Mutex::new(RefCell::new(None));
+
+// I2C interrupt handler, runs the slave state machine
+#[cfg(target_arch = "avr")]
+bind_twi_interrupt!(TWI_INTERRUPT, || interrupt::free(|cs| {
+    if let Some(i2c_slave) = I2C_SLAVE.borrow(cs).borrow_mut().as_mut() {
+        i2c_slave.on_interrupt();
+    }
+}));
+
+// Firmware only runs on the AVR, host builds exist for `cargo test`
+#[cfg(not(target_arch = "avr"))]
+fn main() {}
+
+#[cfg(target_arch = "avr")]
+#[arduino_hal::entry]
+fn main() -> ! {
+    let dp = arduino_hal::Peripherals::take().unwrap();
+    let pins = arduino_hal::pins!(dp);
+    let mut serial = arduino_hal::default_serial!(dp, pins, 9600);
+
+    let mut led = pins.d13.into_output();
+
+    // TWI pins of the selected board
+    #[cfg(any(
+        feature = "arduino-uno",
+        feature = "arduino-nano",
+        feature = "sparkfun-promini-3v3",
+        feature = "sparkfun-promini-5v"
+    ))]
+    let (sda, scl) = (pins.a4, pins.a5);
+    #[cfg(feature = "arduino-leonardo")]
+    let (sda, scl) = (pins.d2, pins.d3);
+    #[cfg(feature = "arduino-mega2560")]
+    let (sda, scl) = (pins.d20, pins.d21);
+
+    // Using external pullup resistors, so pins configured as floating inputs
+    let sda = sda.into_floating_input();
+    let scl = scl.into_floating_input();
+
+    let slave_address: u8 = 0x26;
+
+    let twi = AvrTwi::new(dp.TWI, sda, scl);
+    let mut i2c_slave = I2cSlave::new(twi, slave_address, TWI_INTERRUPT.bind().unwrap());
+
+    // Disabling power reduction for TWI
+    #[cfg(feature = "atmega328p")]
+    dp.CPU.prr.write(|w| w.prtwi().clear_bit());
+    #[cfg(any(feature = "atmega32u4", feature = "atmega2560"))]
+    dp.CPU.prr0.write(|w| w.prtwi().clear_bit());
+
+    i2c_slave.init(false);
+
+    let mut registers = RegisterMap::new([0; REGISTERS]);
+    let mut window = [0; REGISTERS];
+
+    let mut i2c_slave = IrqSlave::new(i2c_slave);
+    registers.read(&mut window);
+    i2c_slave.set_response(&window);
+    i2c_slave.listen();
+
+    interrupt::free(|cs| I2C_SLAVE.borrow(cs).replace(Some(i2c_slave)));
+
+    // Enable global interrupt
+    unsafe { interrupt::enable() };
+
+    ufmt::uwriteln!(&mut serial, "Initialized with addr: 0x{:X}", slave_address).unwrap();
+
+    led.set_low();
+
+    loop {
+        // Transactions are handled in the interrupt, pick up the finished ones
+        let event = interrupt::free(|cs| {
+            let mut i2c_slave = I2C_SLAVE.borrow(cs).borrow_mut();
+            let i2c_slave = i2c_slave.as_mut().unwrap();
+
+            let event = i2c_slave.take_event();
+
+            match event {
+                Some(Ok(Transfer::Received(_))) => {
+                    registers.write(i2c_slave.received());
+
+                    // Multiply each input by 10 just to see difference in master's output clearly
+                    for i in 0..INPUTS {
+                        if registers.is_changed(i) {
+                            let value = registers.registers()[i].wrapping_mul(10);
+                            registers.registers_mut()[INPUTS + i] = value;
+                        }
+                    }
+                }
+                Some(Ok(Transfer::Sent(count))) => registers.advance(count),
+                _ => {}
+            }
+
+            // Next read starts at the register pointer
+            registers.read(&mut window);
+            i2c_slave.set_response(&window);
+
+            event
+        });
+
+        match event {
+            // RECEIVE
+            Some(Ok(Transfer::Received(_))) => {
+                uwrite!(&mut serial, "Registers: ").unwrap();
+
+                registers.registers().iter().for_each(|b| {
+                    uwrite!(&mut serial, "{} ", *b).unwrap();
+                });
+                uwrite!(&mut serial, "\n").unwrap();
+
+                registers.clear_changed();
+            }
+            // RESPOND
+            Some(Ok(Transfer::Sent(count))) => {
+                uwriteln!(&mut serial, "{} bytes has been sent back\n", count).unwrap();
+            }
+            Some(Err(err)) => {
+                uwriteln!(&mut serial, "Error: {:?}", err).unwrap();
+            }
+            Some(Ok(Transfer::GeneralCall(_))) | None => {}
+        };
+    }
+}
@@ -27,6 +27,7 @@
           buildInputs = [ ravedude-flake.packages.${system}.default ];
           shellHook = ''
             export RAVEDUDE_PORT=/dev/tty.usbserial-0001
+            export RAVEDUDE_BOARD=promini-5v
           '';
         };
       }
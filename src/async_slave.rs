@@ -0,0 +1,252 @@
+//! async/await interface.
+//!
+//! [`AsyncSlave`] polls the same state machine as the non-blocking API, but
+//! instead of spinning on the interrupt flag the task is woken from the TWI
+//! interrupt handler through a [`TwiWaker`]. Nothing is allocated, the waker
+//! lives in a static next to the [`TwiInterrupt`](crate::twi_interrupt::TwiInterrupt).
+//!
+//! The interrupt handler masks the interrupt until the driver clears TWINT and
+//! wakes the task, e.g.
+//!
+//! ```ignore
+//! static TWI_WAKER: TwiWaker = TwiWaker::new();
+//!
+//! bind_twi_interrupt!(TWI_INTERRUPT, || {
+//!     unsafe { <AvrTwi>::mask_interrupt() };
+//!     TWI_WAKER.wake();
+//! });
+//! ```
+
+use core::{
+    cell::Cell,
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+
+use crate::{
+    i2c_slave::{I2CSlaveError, I2cSlave, Summary, Transfer},
+    twi::Twi,
+    twi_interrupt::free,
+};
+
+/// Waker of the task waiting for the next TWI interrupt
+pub struct TwiWaker {
+    waker: Cell<Option<Waker>>,
+}
+
+// Only accessed inside critical sections
+unsafe impl Sync for TwiWaker {}
+
+impl Default for TwiWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TwiWaker {
+    pub const fn new() -> Self {
+        Self {
+            waker: Cell::new(None),
+        }
+    }
+
+    /// Wake the waiting task, called from the TWI interrupt handler
+    pub fn wake(&self) {
+        if let Some(waker) = free(|| self.waker.take()) {
+            waker.wake();
+        }
+    }
+
+    /// Remember the waker of the task polling the slave
+    fn register(&self, waker: &Waker) {
+        free(|| {
+            let waker = match self.waker.take() {
+                Some(old) if old.will_wake(waker) => old,
+                _ => waker.clone(),
+            };
+
+            self.waker.set(Some(waker));
+        });
+    }
+}
+
+fn ready<R>(result: nb::Result<R, I2CSlaveError>) -> Poll<Result<R, I2CSlaveError>> {
+    match result {
+        Err(nb::Error::WouldBlock) => Poll::Pending,
+        Err(nb::Error::Other(err)) => Poll::Ready(Err(err)),
+        Ok(value) => Poll::Ready(Ok(value)),
+    }
+}
+
+pub struct AsyncSlave<'a, T: Twi> {
+    slave: I2cSlave<'a, T>,
+    waker: &'a TwiWaker,
+}
+
+impl<'a, T: Twi> AsyncSlave<'a, T> {
+    /// `waker` has to be woken by the interrupt handler that signals the
+    /// interrupt of `slave`.
+    pub fn new(slave: I2cSlave<'a, T>, waker: &'a TwiWaker) -> Self {
+        Self { slave, waker }
+    }
+
+    /// Serve the next transaction in whichever direction the master starts
+    /// it, see [`I2cSlave::transaction`].
+    // Buffers are used mutably inside `poll_fn`, which the lint misses
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub async fn listen<F>(
+        &mut self,
+        rx: &mut [u8],
+        tx: &mut [u8],
+        respond: F,
+    ) -> Result<Transfer, I2CSlaveError>
+    where
+        F: FnOnce(&[u8], &mut [u8]) -> usize,
+    {
+        let mut respond = Some(|_, written: &[u8], tx: &mut [u8]| respond(written, tx));
+        let mut tx_len = 0;
+
+        poll_fn(|cx| {
+            // Registered before the flag is checked, so no wakeup gets lost
+            self.waker.register(cx.waker());
+
+            ready(
+                self.slave
+                    .poll_transaction(rx, tx, &mut tx_len, &mut respond),
+            )
+        })
+        .await
+    }
+
+    /// Receive data and write it to buffer, see [`I2cSlave::receive`]
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    pub async fn receive(&mut self, buffer: &mut [u8]) -> Result<Summary, I2CSlaveError> {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+
+            ready(self.slave.try_receive(buffer))
+        })
+        .await
+    }
+
+    /// Send buffer to the master, see [`I2cSlave::respond`]
+    pub async fn respond(&mut self, buffer: &[u8]) -> Result<Summary, I2CSlaveError> {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+
+            ready(self.slave.try_respond(buffer))
+        })
+        .await
+    }
+
+    /// release moved values
+    pub fn release(self) -> I2cSlave<'a, T> {
+        self.slave
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        future::Future,
+        pin::pin,
+        sync::atomic::{AtomicBool, Ordering},
+        task::{Context, Poll},
+    };
+    use std::{
+        sync::Arc,
+        task::{Wake, Waker},
+    };
+
+    use super::{AsyncSlave, TwiWaker};
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::Transfer,
+        twi_interrupt::TwiInterrupt,
+    };
+
+    struct Woken(AtomicBool);
+
+    impl Wake for Woken {
+        fn wake(self: Arc<Self>) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    /// Poll `future` to completion, running the interrupt handler whenever
+    /// the bus raises the flag. Panics if the future is never woken.
+    fn block_on<F: Future>(flag: &TwiInterrupt, twi_waker: &TwiWaker, future: F) -> F::Output {
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let waker = Waker::from(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = pin!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            // TWI interrupt
+            assert!(flag.is_pending(), "no interrupt pending");
+            twi_waker.wake();
+
+            assert!(woken.0.swap(false, Ordering::SeqCst), "task not woken");
+        }
+    }
+
+    fn slave<'a>(waker: &'a TwiWaker, bus: SimBus<'a>) -> AsyncSlave<'a, SimBus<'a>> {
+        AsyncSlave::new(sim_slave(bus), waker)
+    }
+
+    #[test]
+    fn receive_then_respond() {
+        let flag = TwiInterrupt::new();
+        let waker = TwiWaker::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3])
+            .stop()
+            .read(ADDR, 3)
+            .stop();
+        let mut slave = slave(&waker, bus);
+        let mut buf = [0; 4];
+
+        assert!(block_on(&flag, &waker, slave.receive(&mut buf)).is_ok());
+        assert_eq!(buf, [1, 2, 3, 0]);
+
+        assert!(matches!(
+            block_on(&flag, &waker, slave.respond(&[10, 20, 30])),
+            Ok(summary) if summary.count == 3
+        ));
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [10, 20, 30]);
+    }
+
+    #[test]
+    fn listen_write_then_read() {
+        let flag = TwiInterrupt::new();
+        let waker = TwiWaker::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[5]).read(ADDR, 2).stop();
+        let mut slave = slave(&waker, bus);
+        let mut rx = [0; 2];
+        let mut tx = [0; 2];
+
+        assert!(matches!(
+            block_on(&flag, &waker, slave.listen(&mut rx, &mut tx, |_, _| 0)),
+            Ok(Transfer::Received(1))
+        ));
+
+        let respond = |written: &[u8], tx: &mut [u8]| {
+            tx[0] = written[0] * 10;
+            tx[1] = written[0] * 20;
+            2
+        };
+        assert!(matches!(
+            block_on(&flag, &waker, slave.listen(&mut rx, &mut tx, respond)),
+            Ok(Transfer::Sent(2))
+        ));
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [50, 100]);
+    }
+}
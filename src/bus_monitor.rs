@@ -0,0 +1,176 @@
+//! Stuck bus detection.
+//!
+//! [`BusMonitor`] samples SDA and SCL through the pins owned by the TWI and
+//! reports a line that stays low for a number of consecutive samples. Call
+//! [`BusMonitor::sample`] periodically, e.g. from a timer or the main loop; the
+//! threshold is counted in those calls.
+
+use crate::{i2c_slave::I2cSlave, twi::Twi};
+
+/// Side keeping a line low
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Holder {
+    /// TWINT isn't set, the master or another device holds the line. So does
+    /// a TWI stuck mid-byte, which [`I2cSlave::recover_bus`] releases.
+    Master,
+    /// The TWI is waiting for the driver with TWINT set
+    Slave,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BusHealth {
+    Ok,
+    /// Lines low for longer than the threshold
+    Stuck {
+        sda: bool,
+        scl: bool,
+        holder: Holder,
+    },
+}
+
+pub struct BusMonitor {
+    threshold: u32,
+    sda_low: u32,
+    scl_low: u32,
+}
+
+impl BusMonitor {
+    /// Report a line as stuck after `threshold` consecutive low samples
+    pub const fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            sda_low: 0,
+            scl_low: 0,
+        }
+    }
+
+    /// Sample both lines once
+    pub fn sample<T: Twi>(&mut self, slave: &mut I2cSlave<T>) -> BusHealth {
+        let (sda, scl) = slave.lines();
+
+        self.sda_low = if sda {
+            0
+        } else {
+            self.sda_low.saturating_add(1)
+        };
+        self.scl_low = if scl {
+            0
+        } else {
+            self.scl_low.saturating_add(1)
+        };
+
+        let sda = self.sda_low >= self.threshold;
+        let scl = self.scl_low >= self.threshold;
+
+        if !sda && !scl {
+            return BusHealth::Ok;
+        }
+
+        let holder = if slave.holding_bus() {
+            Holder::Slave
+        } else {
+            Holder::Master
+        };
+
+        BusHealth::Stuck { sda, scl, holder }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BusHealth, BusMonitor, Holder};
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::{I2cSlave, Transfer},
+        twi_interrupt::TwiInterrupt,
+        twi_mock::MockTwi,
+    };
+
+    #[test]
+    fn master_holding_sda() {
+        let flag = TwiInterrupt::new();
+        let mut twi = MockTwi::new(&flag, &[]);
+        twi.sda = false;
+
+        let mut slave = I2cSlave::new(twi, ADDR, flag.bind().unwrap());
+        let mut monitor = BusMonitor::new(3);
+
+        assert_eq!(monitor.sample(&mut slave), BusHealth::Ok);
+        assert_eq!(monitor.sample(&mut slave), BusHealth::Ok);
+        assert_eq!(
+            monitor.sample(&mut slave),
+            BusHealth::Stuck {
+                sda: true,
+                scl: false,
+                holder: Holder::Master
+            }
+        );
+    }
+
+    #[test]
+    fn slave_holding_scl_is_recovered() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1]).stop();
+        let mut slave = sim_slave(bus);
+        let mut monitor = BusMonitor::new(2);
+
+        // Addressed, but the application never services the TWI
+        slave.arm();
+
+        monitor.sample(&mut slave);
+        assert_eq!(
+            monitor.sample(&mut slave),
+            BusHealth::Stuck {
+                sda: false,
+                scl: true,
+                holder: Holder::Slave
+            }
+        );
+
+        slave.recover_bus();
+        assert_eq!(monitor.sample(&mut slave), BusHealth::Ok);
+
+        let bus = finished(slave);
+        assert_eq!(bus.acks, [false]);
+    }
+
+    #[test]
+    fn stuck_sda_is_recovered() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1])
+            .stop()
+            .stuck_sda()
+            .write(ADDR, &[2])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut monitor = BusMonitor::new(2);
+        let mut rx = [0; 2];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Ok(Transfer::Received(1))
+        ));
+
+        monitor.sample(&mut slave);
+        assert_eq!(
+            monitor.sample(&mut slave),
+            BusHealth::Stuck {
+                sda: true,
+                scl: false,
+                holder: Holder::Master
+            }
+        );
+
+        // Master gets the bus back and carries on
+        slave.recover_bus();
+        assert_eq!(monitor.sample(&mut slave), BusHealth::Ok);
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Ok(Transfer::Received(1))
+        ));
+        assert_eq!(rx[0], 2);
+
+        finished(slave);
+    }
+}
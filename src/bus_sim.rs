@@ -0,0 +1,816 @@
+//! Behavioural model of an I2C bus with a simulated master, for host-side tests.
+//!
+//! [`SimBus`] implements [`Twi`] and replays a master script against the
+//! slave. Unlike [`MockTwi`](crate::twi_mock::MockTwi) it doesn't replay fixed
+//! status codes: ACK/NACK of every byte follows the TWEA/TWEN bits the driver
+//! writes, and the status codes are the ones the datasheet defines for the
+//! resulting bus state.
+//!
+//! The master waits on SCL while TWINT is set, like a real master does while
+//! the slave stretches the clock. A START after a STOP is only issued once the
+//! slave listens, modelling a master that retries until it's ACKed; a repeated
+//! START follows immediately.
+
+use std::vec::Vec;
+
+use crate::{
+    i2c_slave::I2cSlave,
+    twi::{Twi, TWEA, TWEN, TWGCE, TWIE, TWINT},
+    twi_interrupt::TwiInterrupt,
+};
+
+/// Address of the slave under test
+pub const ADDR: u8 = 0x26;
+
+/// Initialized slave at [`ADDR`] on `bus`, bound to the interrupt it signals
+pub fn sim_slave(bus: SimBus<'_>) -> I2cSlave<'_, SimBus<'_>> {
+    let interrupt = bus.interrupt;
+    let mut slave = I2cSlave::new(bus, ADDR, interrupt.bind().unwrap());
+    slave.init(false);
+
+    slave
+}
+
+/// Bus of `slave`, once the master has run its whole script
+pub fn finished<'a>(slave: I2cSlave<'a, SimBus<'a>>) -> SimBus<'a> {
+    let (bus, _) = slave.split();
+    assert!(bus.done());
+
+    bus
+}
+
+enum Op {
+    Write(u8, Vec<u8>),
+    Read(u8, usize),
+    Stop,
+    StuckSda,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Addressed {
+    Write { general_call: bool },
+    Read,
+}
+
+pub struct SimBus<'a> {
+    interrupt: &'a TwiInterrupt,
+    ops: Vec<Op>,
+    // Next op and byte within it, `None` while the address is pending
+    pc: usize,
+    pos: Option<usize>,
+    // No transaction in progress, next START follows a STOP
+    idle: bool,
+    addressed: Option<Addressed>,
+    pending: bool,
+    // Slave TWI drives SDA low until it's disabled
+    sda_stuck: bool,
+    status: u8,
+    twdr: u8,
+    twar: u8,
+    twamr: u8,
+    twcr: u8,
+    /// ACK of every SLA+R/W sent by the master
+    pub address_acks: Vec<bool>,
+    /// ACK of every data byte written by the master
+    pub acks: Vec<bool>,
+    /// Every data byte read by the master
+    pub read: Vec<u8>,
+}
+
+impl<'a> SimBus<'a> {
+    pub fn new(interrupt: &'a TwiInterrupt) -> Self {
+        Self {
+            interrupt,
+            ops: Vec::new(),
+            pc: 0,
+            pos: None,
+            idle: true,
+            addressed: None,
+            pending: false,
+            sda_stuck: false,
+            status: 0xF8,
+            twdr: 0,
+            twar: 0,
+            twamr: 0,
+            twcr: 0,
+            address_acks: Vec::new(),
+            acks: Vec::new(),
+            read: Vec::new(),
+        }
+    }
+
+    /// START (or repeated START), SLA+W and `data`.
+    pub fn write(mut self, addr: u8, data: &[u8]) -> Self {
+        self.ops.push(Op::Write(addr, data.to_vec()));
+        self
+    }
+
+    /// START (or repeated START), SLA+R and `len` bytes, NACKing the last.
+    pub fn read(mut self, addr: u8, len: usize) -> Self {
+        self.ops.push(Op::Read(addr, len));
+        self
+    }
+
+    /// STOP condition.
+    pub fn stop(mut self) -> Self {
+        self.ops.push(Op::Stop);
+        self
+    }
+
+    /// The slave's TWI gets stuck driving SDA low, e.g. after a glitch
+    /// mid-byte, until it's disabled. The master waits for the bus meanwhile.
+    pub fn stuck_sda(mut self) -> Self {
+        self.ops.push(Op::StuckSda);
+        self
+    }
+
+    /// Master has run its whole script
+    pub fn done(&self) -> bool {
+        self.pc == self.ops.len()
+    }
+
+    fn enabled(&self) -> bool {
+        self.twcr & TWEN != 0
+    }
+
+    fn listening(&self) -> bool {
+        self.enabled() && self.twcr & TWEA != 0
+    }
+
+    fn matches(&self, addr: u8) -> bool {
+        let ignored = self.twamr >> 1;
+
+        (addr ^ (self.twar >> 1)) & !ignored == 0 || (addr == 0 && self.twar & TWGCE != 0)
+    }
+
+    fn raise(&mut self, status: u8) {
+        self.status = status;
+        self.pending = true;
+        self.interrupt.signal();
+    }
+
+    fn next_op(&mut self) {
+        self.pc += 1;
+        self.pos = None;
+    }
+
+    /// Run the master until the slave has to act on TWINT or the master waits.
+    fn advance(&mut self) {
+        while !self.pending && !self.sda_stuck {
+            let (addr, len, write) = match self.ops.get(self.pc) {
+                None => return,
+                Some(Op::StuckSda) => {
+                    self.next_op();
+                    self.sda_stuck = true;
+                    continue;
+                }
+                Some(Op::Stop) => {
+                    self.next_op();
+                    self.idle = true;
+
+                    if self.addressed.take().is_some() {
+                        self.raise(0xA0);
+                    }
+                    continue;
+                }
+                Some(Op::Write(addr, data)) => (*addr, data.len(), true),
+                Some(Op::Read(addr, len)) => (*addr, *len, false),
+            };
+
+            let Some(i) = self.pos else {
+                if self.idle && !self.listening() {
+                    // Master retries until the slave is back
+                    return;
+                }
+                self.idle = false;
+
+                // Repeated START while still addressed
+                if self.addressed.take().is_some() {
+                    self.raise(0xA0);
+                    continue;
+                }
+
+                let general_call = addr == 0;
+                if !self.listening() || !self.matches(addr) || (general_call && !write) {
+                    // SLA NACKed, master gives up on this message
+                    self.address_acks.push(false);
+                    self.next_op();
+                    continue;
+                }
+
+                self.address_acks.push(true);
+                self.pos = Some(0);
+                self.twdr = addr << 1 | u8::from(!write);
+
+                if write {
+                    self.addressed = Some(Addressed::Write { general_call });
+                    self.raise(if general_call { 0x70 } else { 0x60 });
+                } else {
+                    self.addressed = Some(Addressed::Read);
+                    self.raise(0xA8);
+                }
+                continue;
+            };
+
+            if i == len {
+                self.next_op();
+                continue;
+            }
+
+            if write {
+                let Op::Write(_, data) = &self.ops[self.pc] else {
+                    unreachable!()
+                };
+                let byte = data[i];
+
+                let Some(Addressed::Write { general_call }) = self.addressed else {
+                    // Nobody ACKs, master gives up on this message
+                    self.acks.push(false);
+                    self.next_op();
+                    continue;
+                };
+
+                self.twdr = byte;
+
+                if self.listening() {
+                    self.acks.push(true);
+                    self.pos = Some(i + 1);
+                    self.raise(if general_call { 0x90 } else { 0x80 });
+                } else {
+                    // NACK returned, slave switches to not addressed mode
+                    self.acks.push(false);
+                    self.addressed = None;
+                    self.next_op();
+                    self.raise(if general_call { 0x98 } else { 0x88 });
+                }
+            } else {
+                if self.addressed != Some(Addressed::Read) {
+                    // SDA is released, master reads ones
+                    self.read.extend((i..len).map(|_| 0xFF));
+                    self.next_op();
+                    continue;
+                }
+
+                self.read.push(self.twdr);
+                self.pos = Some(i + 1);
+
+                if i + 1 == len {
+                    // Master NACKs the last byte
+                    self.addressed = None;
+                    self.next_op();
+                    self.raise(0xC0);
+                } else if self.twcr & TWEA != 0 {
+                    self.raise(0xB8);
+                } else {
+                    // Slave sent its last byte but master ACKed
+                    self.addressed = None;
+                    self.raise(0xC8);
+                }
+            }
+        }
+    }
+}
+
+impl Twi for SimBus<'_> {
+    fn status(&mut self) -> u8 {
+        if self.pending {
+            self.status
+        } else {
+            0xF8
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        self.twdr
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.twdr = data;
+    }
+
+    fn write_address(&mut self, twar: u8) {
+        self.twar = twar;
+    }
+
+    fn write_address_mask(&mut self, twamr: u8) {
+        self.twamr = twamr;
+    }
+
+    fn write_control(&mut self, twcr: u8) {
+        self.twcr = twcr;
+
+        if twcr & TWINT != 0 {
+            self.pending = false;
+        }
+
+        // Disabled TWI releases the bus and forgets the transfer
+        if !self.enabled() {
+            self.addressed = None;
+            self.pending = false;
+            self.sda_stuck = false;
+        }
+
+        // Interrupt fires again while TWINT is still set
+        if self.pending && twcr & TWIE != 0 {
+            self.interrupt.signal();
+        }
+
+        self.advance();
+    }
+
+    fn reset_control(&mut self) {
+        self.write_control(0);
+    }
+
+    fn sda_high(&mut self) -> bool {
+        !self.sda_stuck
+    }
+
+    // Slave stretches the clock while TWINT is set
+    fn scl_high(&mut self) -> bool {
+        !self.pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{finished, sim_slave, SimBus, ADDR};
+    use std::{vec, vec::Vec};
+
+    use crate::{
+        i2c_slave::{End, I2CSlaveError, ReadOverflow, Summary, Transfer, WriteOverflow},
+        twi_interrupt::TwiInterrupt,
+    };
+
+    #[test]
+    fn master_writes_3_then_reads_4() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3])
+            .stop()
+            .read(ADDR, 4)
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 4];
+
+        assert!(slave.receive(&mut buf).is_ok());
+        assert_eq!(buf, [1, 2, 3, 0]);
+
+        assert!(matches!(
+            slave.respond(&[10, 20, 30, 40]),
+            Ok(Summary {
+                count: 4,
+                end: End::MasterNack,
+                ..
+            })
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [true, true]);
+        assert_eq!(bus.acks, [true, true, true]);
+        assert_eq!(bus.read, [10, 20, 30, 40]);
+    }
+
+    #[test]
+    fn master_writes_past_buffer() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1, 2, 3, 4, 5]).stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 4];
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::BufferOverflow)
+        ));
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        finished(slave);
+    }
+
+    /// Summary, buffer and ACKs of the master writing `data` to a 3 byte buffer
+    fn write_past(policy: WriteOverflow, data: &[u8]) -> (Summary, [u8; 3], Vec<bool>) {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, data).stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 3];
+
+        slave.set_write_overflow(policy);
+
+        let summary = slave.receive(&mut buf).ok().unwrap();
+        assert_eq!(summary.count, 3);
+
+        let bus = finished(slave);
+        (summary, buf, bus.acks)
+    }
+
+    #[test]
+    fn write_overflow_policies() {
+        let data = [1, 2, 3, 4, 5];
+
+        let (summary, buf, acks) = write_past(WriteOverflow::Nack, &data);
+        assert!(summary.end == End::Nacked);
+        assert_eq!((summary.dropped, buf), (1, [1, 2, 3]));
+        assert_eq!(acks, [true, true, true, false]);
+
+        let (summary, buf, acks) = write_past(WriteOverflow::Drop, &data);
+        assert_eq!((summary.dropped, buf), (2, [1, 2, 3]));
+        assert_eq!(acks, [true; 5]);
+
+        let (summary, buf, _) = write_past(WriteOverflow::KeepNewest, &data);
+        assert_eq!((summary.dropped, buf), (2, [3, 4, 5]));
+
+        // Several times around the ring
+        let (summary, buf, _) = write_past(WriteOverflow::KeepNewest, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!((summary.dropped, buf), (5, [6, 7, 8]));
+
+        // Exactly fitting write isn't NACKed
+        let (summary, buf, acks) = write_past(WriteOverflow::Nack, &data[..3]);
+        assert!(summary.end == End::Stop);
+        assert_eq!((summary.dropped, buf), (0, [1, 2, 3]));
+        assert_eq!(acks, [true; 3]);
+    }
+
+    #[test]
+    fn read_after_overflow_drops_nothing() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3, 4])
+            .stop()
+            .read(ADDR, 1)
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 2];
+
+        slave.set_write_overflow(WriteOverflow::Drop);
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary { dropped: 2, .. })
+        ));
+        assert!(matches!(
+            slave.respond(&[10]),
+            Ok(Summary { dropped: 0, .. })
+        ));
+        assert_eq!(slave.dropped(), 0);
+
+        finished(slave);
+    }
+
+    #[test]
+    fn write_overflow_abort_while_listening() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1, 2, 3, 4]).stop();
+        let mut slave = sim_slave(bus);
+        let mut rx = [0; 2];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Err(I2CSlaveError::BufferOverflow)
+        ));
+
+        // First byte past the buffer is ACKed, the next one NACKed
+        assert_eq!(slave.dropped(), 2);
+
+        let bus = finished(slave);
+        assert_eq!(bus.acks, [true, true, true, false]);
+    }
+
+    #[test]
+    fn master_reads_less_than_buffer() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 2).stop();
+        let mut slave = sim_slave(bus);
+        assert!(matches!(
+            slave.respond(&[10, 20, 30, 40]),
+            Ok(summary) if summary.count == 2 && summary.short_read()
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 20]);
+    }
+
+    #[test]
+    fn master_reads_past_buffer() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 5).stop();
+        let mut slave = sim_slave(bus);
+        assert!(matches!(
+            slave.respond(&[10, 20, 30]),
+            Ok(Summary {
+                count: 3,
+                end: End::ReadPastEnd,
+                ..
+            })
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 20, 30, 0x00, 0xFF]);
+    }
+
+    /// Bytes the master gets reading `len` bytes of `response`, and the count
+    /// of sent bytes
+    fn read_past(policy: ReadOverflow, response: &[u8], len: usize) -> (Vec<u8>, usize) {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, len).stop();
+        let mut slave = sim_slave(bus);
+
+        slave.set_read_overflow(policy);
+
+        let summary = slave.respond(response).ok().unwrap();
+        assert!(summary.end == End::ReadPastEnd);
+        assert!(!summary.short_read());
+
+        let bus = finished(slave);
+        (bus.read, summary.count)
+    }
+
+    #[test]
+    fn read_overflow_policies() {
+        let response = [1, 2, 3];
+
+        assert_eq!(
+            read_past(ReadOverflow::Refuse, &response, 6),
+            (vec![1, 2, 3, 0x00, 0xFF, 0xFF], 3)
+        );
+        assert_eq!(
+            read_past(ReadOverflow::Pad(0xEE), &response, 6),
+            (vec![1, 2, 3, 0xEE, 0xEE, 0xEE], 6)
+        );
+        assert_eq!(
+            read_past(ReadOverflow::Wrap, &response, 10),
+            (vec![1, 2, 3, 1, 2, 3, 1, 2, 3, 1], 10)
+        );
+        assert_eq!(
+            read_past(ReadOverflow::RepeatLast, &response, 5),
+            (vec![1, 2, 3, 3, 3], 5)
+        );
+    }
+
+    #[test]
+    fn read_overflow_empty_response() {
+        assert_eq!(
+            read_past(ReadOverflow::Refuse, &[], 3),
+            (vec![0x00, 0xFF, 0xFF], 0)
+        );
+        assert_eq!(
+            read_past(ReadOverflow::Pad(0xEE), &[], 2),
+            (vec![0xEE, 0xEE], 2)
+        );
+        assert_eq!(read_past(ReadOverflow::Wrap, &[], 2), (vec![0x00, 0xFF], 0));
+        assert_eq!(
+            read_past(ReadOverflow::RepeatLast, &[], 2),
+            (vec![0x00, 0xFF], 0)
+        );
+    }
+
+    #[test]
+    fn unexpected_direction_is_nacked() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1, 2]).stop();
+        let mut slave = sim_slave(bus);
+        assert!(matches!(
+            slave.respond(&[10]),
+            Err(I2CSlaveError::NotExpectedTransactionDirection)
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.acks, [false]);
+    }
+
+    #[test]
+    fn repeated_start_read_is_nacked_after_receive() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1]).read(ADDR, 2).stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 1];
+
+        assert!(slave.receive(&mut buf).is_ok());
+        assert_eq!(buf, [1]);
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [true, false]);
+    }
+
+    #[test]
+    fn other_address_is_ignored() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(0x10, &[1])
+            .stop()
+            .write(ADDR, &[2])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 1];
+
+        assert!(slave.receive(&mut buf).is_ok());
+        assert_eq!(buf, [2]);
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [false, true]);
+    }
+
+    #[test]
+    fn address_mask_matches_block() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(0x24, &[1])
+            .stop()
+            .write(0x27, &[2])
+            .stop()
+            .write(0x28, &[3])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 1];
+
+        // 0x24..=0x27
+        slave.set_address_mask(0x03);
+        slave.init(false);
+
+        assert!(slave.receive(&mut buf).is_ok());
+        assert_eq!((slave.matched_address(), buf[0]), (0x24, 1));
+        assert!(slave.receive(&mut buf).is_ok());
+        assert_eq!((slave.matched_address(), buf[0]), (0x27, 2));
+
+        // Master gives up on 0x28 right away
+        assert!(matches!(
+            slave.try_receive(&mut buf),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [true, true, false]);
+    }
+
+    #[test]
+    fn general_call_is_told_apart() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(0, &[1, 2])
+            .stop()
+            .write(ADDR, &[3])
+            .stop()
+            .write(0, &[4])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 2];
+
+        slave.init(true);
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary {
+                count: 2,
+                general_call: true,
+                ..
+            })
+        ));
+        assert_eq!(slave.matched_address(), 0);
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary {
+                count: 1,
+                general_call: false,
+                ..
+            })
+        ));
+        assert_eq!(slave.matched_address(), ADDR);
+
+        // Write isn't expected while responding
+        assert!(matches!(
+            slave.respond(&[1]),
+            Err(I2CSlaveError::NotExpectedTransactionDirection)
+        ));
+    }
+
+    #[test]
+    fn general_call_reloads_hardware_address() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(0, &[0x04])
+            .stop()
+            .write(0x30, &[1])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut buf = [0; 2];
+
+        slave.set_hardware_address(Some(|| 0x30));
+        slave.init(true);
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary {
+                general_call: true,
+                ..
+            })
+        ));
+        assert_eq!(slave.address(), 0x30);
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary {
+                general_call: false,
+                ..
+            })
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [true, true]);
+    }
+
+    #[test]
+    fn general_call_reset_discards_last_write() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[2])
+            .stop()
+            .write(0, &[0x06])
+            .stop()
+            .read(ADDR, 3)
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut rx = [0; 4];
+        let mut tx = [0; 6];
+
+        slave.set_hardware_address(Some(|| ADDR));
+        slave.init(true);
+        for _ in 0..3 {
+            assert!(slave.transaction(&mut rx, &mut tx, registers).is_ok());
+        }
+
+        // Read starts from the first register, not the pointer written before
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 11, 12]);
+    }
+
+    /// Respond with the registers from the written register pointer on
+    fn registers(written: &[u8], tx: &mut [u8]) -> usize {
+        let regs = [10, 11, 12, 13, 14, 15];
+        let from = written.first().map_or(0, |&ptr| ptr as usize);
+
+        let data = &regs[from.min(regs.len())..];
+        tx[..data.len()].copy_from_slice(data);
+        data.len()
+    }
+
+    #[test]
+    fn write_repeated_start_read() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[2]).read(ADDR, 3).stop();
+        let mut slave = sim_slave(bus);
+        let mut rx = [0; 4];
+        let mut tx = [0; 8];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut tx, registers),
+            Ok(Transfer::Received(1))
+        ));
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut tx, registers),
+            Ok(Transfer::Sent(3))
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.address_acks, [true, true]);
+        assert_eq!(bus.read, [12, 13, 14]);
+    }
+
+    #[test]
+    fn transaction_read_without_write() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 2).stop();
+        let mut slave = sim_slave(bus);
+        let mut rx = [0; 4];
+        let mut tx = [0; 8];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut tx, registers),
+            Ok(Transfer::Sent(2))
+        ));
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 11]);
+    }
+
+    #[test]
+    fn transaction_write_only() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3])
+            .stop()
+            .write(ADDR, &[4])
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut rx = [0; 4];
+        let mut tx = [0; 8];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut tx, registers),
+            Ok(Transfer::Received(3))
+        ));
+        assert_eq!(rx[..3], [1, 2, 3]);
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut tx, registers),
+            Ok(Transfer::Received(1))
+        ));
+        assert_eq!(rx[..1], [4]);
+
+        finished(slave);
+    }
+}
@@ -0,0 +1,244 @@
+//! Callback interface modelled on Arduino Wire `onReceive`/`onRequest`.
+//!
+//! The application implements [`Handler`] once and the driver calls it from
+//! the state machine in whichever direction the master goes, either from the
+//! TWI interrupt handler with [`HandlerSlave`] or from the main loop with
+//! [`I2cSlave::serve`].
+
+use crate::{
+    i2c_slave::{I2CSlaveError, I2cSlave, Step, Transfer},
+    twi::Twi,
+};
+
+pub trait Handler {
+    /// Slave has been addressed with `address`, before any data of the
+    /// transaction. Tells apart the devices emulated behind an address mask.
+    fn on_address(&mut self, _address: u8) {}
+
+    /// Master has written `data`, called on the STOP or repeated START
+    /// ending the write.
+    fn on_receive(&mut self, data: &[u8]);
+
+    /// Master has written `data` through the general call address, e.g. the
+    /// `0x06` reset command. Ignored unless implemented.
+    fn on_general_call(&mut self, _data: &[u8]) {}
+
+    /// Master requests data, fill `tx` and return the number of bytes to send.
+    /// The master gets `0x00` once they run out.
+    fn on_request(&mut self, tx: &mut [u8]) -> usize;
+
+    /// Transaction has ended, after [`Handler::on_receive`] or
+    /// [`Handler::on_general_call`] for a write.
+    fn on_stop(&mut self, _result: &Result<Transfer, I2CSlaveError>) {}
+}
+
+impl<'a, T: Twi> I2cSlave<'a, T> {
+    /// Serve the next transaction with `handler`, see
+    /// [`I2cSlave::transaction`] for the buffers.
+    pub fn serve<H: Handler>(
+        &mut self,
+        handler: &mut H,
+        rx: &mut [u8],
+        tx: &mut [u8],
+    ) -> Result<Transfer, I2CSlaveError> {
+        let mut tx_len = 0;
+
+        self.arm_once();
+
+        loop {
+            if let Err(err) = self.wait_listening() {
+                let result = Err(err);
+                handler.on_stop(&result);
+
+                return result;
+            }
+
+            self.take_interrupt();
+
+            if let Step::Done(result) = self.handle(handler, rx, tx, &mut tx_len) {
+                return result;
+            }
+        }
+    }
+
+    /// Handle one TWI interrupt, calling `handler` as the transaction goes.
+    /// `tx_len` carries the response between calls.
+    fn handle<H: Handler>(
+        &mut self,
+        handler: &mut H,
+        rx: &mut [u8],
+        tx: &mut [u8],
+        tx_len: &mut usize,
+    ) -> Step {
+        if let Some(address) = self.latch_address() {
+            handler.on_address(address);
+        }
+
+        // Own SLA+R has been received, data is needed right now
+        if self.status() == 0xA8 {
+            *tx_len = handler.on_request(tx).min(tx.len());
+        }
+
+        let step = self.step(None, rx, &tx[..*tx_len]);
+
+        if let Step::Done(result) = &step {
+            match result {
+                Ok(Transfer::Received(count)) => handler.on_receive(&rx[..*count]),
+                Ok(Transfer::GeneralCall(count)) => handler.on_general_call(&rx[..*count]),
+                _ => {}
+            }
+            handler.on_stop(result);
+        }
+
+        step
+    }
+}
+
+/// Interrupt driven slave calling a [`Handler`] from the TWI interrupt
+pub struct HandlerSlave<'a, T: Twi, H: Handler, const N: usize> {
+    slave: I2cSlave<'a, T>,
+    handler: H,
+    rx: [u8; N],
+    tx: [u8; N],
+    tx_len: usize,
+}
+
+impl<'a, T: Twi, H: Handler, const N: usize> HandlerSlave<'a, T, H, N> {
+    /// Dispatch the transactions of `slave` to `handler`, driven by
+    /// [`HandlerSlave::on_interrupt`].
+    pub fn new(slave: I2cSlave<'a, T>, handler: H) -> Self {
+        Self {
+            slave,
+            handler,
+            rx: [0; N],
+            tx: [0; N],
+            tx_len: 0,
+        }
+    }
+
+    /// Arm the TWI, from now on the handler is called from the interrupt.
+    pub fn listen(&mut self) {
+        self.slave.arm();
+    }
+
+    /// Handle the TWI interrupt.
+    pub fn on_interrupt(&mut self) {
+        self.slave.take_interrupt();
+        self.slave.handle(
+            &mut self.handler,
+            &mut self.rx,
+            &mut self.tx,
+            &mut self.tx_len,
+        );
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// release moved values
+    pub fn release(self) -> (I2cSlave<'a, T>, H) {
+        (self.slave, self.handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::{Handler, HandlerSlave};
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::{I2CSlaveError, Transfer},
+        twi_interrupt::TwiInterrupt,
+    };
+
+    /// Answers with the last write multiplied by 10
+    #[derive(Default)]
+    struct Times10 {
+        addresses: Vec<u8>,
+        last: Vec<u8>,
+        calls: Vec<&'static str>,
+    }
+
+    impl Handler for Times10 {
+        fn on_address(&mut self, address: u8) {
+            self.addresses.push(address);
+            self.calls.push("address");
+        }
+
+        fn on_receive(&mut self, data: &[u8]) {
+            self.last = data.to_vec();
+            self.calls.push("receive");
+        }
+
+        fn on_request(&mut self, tx: &mut [u8]) -> usize {
+            for (byte, value) in tx.iter_mut().zip(&self.last) {
+                *byte = value * 10;
+            }
+            self.calls.push("request");
+
+            self.last.len()
+        }
+
+        fn on_stop(&mut self, _result: &Result<Transfer, I2CSlaveError>) {
+            self.calls.push("stop");
+        }
+    }
+
+    const CALLS: [&str; 9] = [
+        "address", "receive", "stop", "address", "request", "stop", "address", "receive", "stop",
+    ];
+
+    fn bus(flag: &TwiInterrupt) -> SimBus<'_> {
+        SimBus::new(flag)
+            .write(ADDR, &[1, 2])
+            .read(ADDR, 2)
+            .stop()
+            .write(ADDR, &[3])
+            .stop()
+    }
+
+    #[test]
+    fn serve_calls_handler() {
+        let flag = TwiInterrupt::new();
+        let mut slave = sim_slave(bus(&flag));
+        let mut handler = Times10::default();
+        let mut rx = [0; 4];
+        let mut tx = [0; 4];
+
+        for _ in 0..3 {
+            assert!(slave.serve(&mut handler, &mut rx, &mut tx).is_ok());
+        }
+
+        assert_eq!(handler.addresses, [ADDR; 3]);
+        assert_eq!(handler.last, [3]);
+        assert_eq!(handler.calls, CALLS);
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 20]);
+    }
+
+    #[test]
+    fn interrupt_calls_handler() {
+        let flag = TwiInterrupt::new();
+        let mut slave = HandlerSlave::<_, _, 4>::new(sim_slave(bus(&flag)), Times10::default());
+        slave.listen();
+
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+
+        assert_eq!(slave.handler().addresses, [ADDR; 3]);
+        assert_eq!(slave.handler().last, [3]);
+        assert_eq!(slave.handler().calls, CALLS);
+
+        let (slave, _) = slave.release();
+        let bus = finished(slave);
+        assert_eq!(bus.read, [10, 20]);
+    }
+}
@@ -1,16 +1,11 @@
 #![warn(clippy::todo, clippy::unimplemented)]
-use core::sync::atomic::{AtomicBool, Ordering};
-
-use arduino_hal::{
-    hal::port::{PC4, PC5},
-    port::{
-        mode::{Floating, Input},
-        Pin,
-    },
-};
-use avr_device::atmega328p::TWI;
 use ufmt::{uDebug, uwrite};
 
+use crate::{
+    twi::{Twi, TWEA, TWEN, TWGCE, TWIE, TWINT, TWSTO},
+    twi_interrupt::TwiBinding,
+};
+
 pub enum I2CSlaveError {
     BufferOverflow,
     UnknownState(u8), // Hex state
@@ -18,12 +13,14 @@ pub enum I2CSlaveError {
     NotImplemented,
     NotExpectedTransactionDirection,
     ArbitrationLost,
+    Timeout,
+    BusError,
 }
 
 impl uDebug for I2CSlaveError {
     fn fmt<W>(&self, f: &mut ufmt::Formatter<'_, W>) -> Result<(), W::Error>
     where
-        W: arduino_hal::prelude::_ufmt_uWrite + ?Sized,
+        W: ufmt::uWrite + ?Sized,
     {
         match self {
             I2CSlaveError::BufferOverflow => uwrite!(f, "BufferOverflow"),
@@ -35,479 +32,1179 @@ impl uDebug for I2CSlaveError {
                 uwrite!(f, "NotExpectedTransactionDirection")
             }
             I2CSlaveError::ArbitrationLost => uwrite!(f, "Arbitration lost"),
+            I2CSlaveError::Timeout => uwrite!(f, "Timeout"),
+            I2CSlaveError::BusError => uwrite!(f, "BusError"),
         }
     }
 }
 
-#[allow(dead_code)]
-pub struct I2cSlave<'a> {
-    twi: TWI,
+/// Direction of a transaction, as seen from the master
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Master writes, slave receives
+    Write,
+    /// Master reads, slave responds
+    Read,
+}
+
+/// Completed transaction
+pub enum Transfer {
+    /// Number of bytes received from the master
+    Received(usize),
+    /// Number of bytes received through the general call address
+    GeneralCall(usize),
+    /// Number of bytes sent to the master
+    Sent(usize),
+}
+
+impl Transfer {
+    /// Number of bytes received, through either address
+    pub fn received(&self) -> Option<usize> {
+        match self {
+            Transfer::Received(count) | Transfer::GeneralCall(count) => Some(*count),
+            Transfer::Sent(_) => None,
+        }
+    }
+}
+
+/// How a transaction ended
+#[derive(Clone, Copy, PartialEq)]
+pub enum End {
+    /// STOP or repeated START, TWSR doesn't tell them apart
+    Stop,
+    /// Slave NACKed a written byte
+    Nacked,
+    /// Master NACKed a byte it read, ending the read
+    MasterNack,
+    /// Master kept reading after the response ran out and got `0x00`
+    ReadPastEnd,
+}
+
+/// Completed single transaction of [`I2cSlave::receive`] or
+/// [`I2cSlave::respond`]
+#[derive(Clone, Copy)]
+pub struct Summary {
+    pub direction: Direction,
+    /// Bytes received, or bytes sent including the ones of the
+    /// [`ReadOverflow`] policy
+    pub count: usize,
+    /// Received through the general call address
+    pub general_call: bool,
+    pub end: End,
+    /// Length of the receive buffer or the response
+    pub len: usize,
+    /// Written bytes that didn't fit the receive buffer, `0` for a read
+    pub dropped: usize,
+}
+
+impl Summary {
+    /// Master stopped reading before the whole response was sent
+    pub fn short_read(&self) -> bool {
+        self.direction == Direction::Read && self.count < self.len
+    }
+}
+
+/// What the master gets when it reads past the end of the response.
+///
+/// Bytes produced by the policy count as sent, the same as the response
+/// itself, so [`Transfer::Sent`] is the number of bytes the master got. The
+/// `0x00` of a refused response isn't counted.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ReadOverflow {
+    /// Send `0x00` once and stop ACKing, the master reads `0xFF` after it
+    Refuse,
+    /// Keep sending the fill byte
+    Pad(u8),
+    /// Start over from the first byte of the response
+    Wrap,
+    /// Keep sending the last byte of the response
+    RepeatLast,
+}
+
+/// What happens to bytes the master writes past the end of the receive buffer
+#[derive(Clone, Copy, PartialEq)]
+pub enum WriteOverflow {
+    /// Fail with [`I2CSlaveError::BufferOverflow`], discarding the write
+    Abort,
+    /// NACK the first byte that doesn't fit, ending the write
+    Nack,
+    /// ACK and drop the bytes that don't fit, keeping the first ones
+    Drop,
+    /// Use the buffer as a ring, keeping the newest bytes
+    KeepNewest,
+}
+
+/// General call second byte: reset and write programmable part of slave address
+pub(crate) const GENERAL_CALL_RESET: u8 = 0x06;
+/// General call second byte: write programmable part of slave address
+const GENERAL_CALL_ADDRESS: u8 = 0x04;
+
+/// Result of handling a single TWI interrupt
+pub(crate) enum Step {
+    Pending,
+    Done(Result<Transfer, I2CSlaveError>),
+}
+
+pub struct I2cSlave<'a, T: Twi> {
+    twi: T,
     addr: u8,
-    sda: Pin<Input<Floating>, PC4>,
-    scl: Pin<Input<Floating>, PC5>,
-    int_flag: &'a AtomicBool,
+    interrupt: TwiBinding<'a>,
+    // Position in the rx/tx buffer of the current transaction
+    pos: usize,
+    // Write has wrapped around rx, the oldest byte is at `pos`
+    wrapped: bool,
+    // Bytes sent in the current read
+    sent: usize,
+    overflow: bool,
+    // TWI is left armed between transactions
+    armed: bool,
+    // Length of the last write, for a read following it
+    written: usize,
+    // Polls of the interrupt flag before giving up, None waits forever
+    timeout: Option<u32>,
+    // Address bits ignored on match
+    mask: u8,
+    // Address of the last SLA+R/W
+    matched: u8,
+    gca: bool,
+    // Current transaction came through the general call address
+    general_call: bool,
+    // How the last transaction ended
+    end: End,
+    // Master has read past the end of the response
+    padded: bool,
+    read_overflow: ReadOverflow,
+    write_overflow: WriteOverflow,
+    // Written bytes of the current transaction not stored
+    dropped: usize,
+    // Source of the programmable address, enables general call commands
+    hardware_address: Option<fn() -> u8>,
 }
 
-impl<'a> I2cSlave<'a> {
-    pub fn new(
-        twi: TWI,
-        addr: u8,
-        sda: Pin<Input<Floating>, PC4>,
-        scl: Pin<Input<Floating>, PC5>,
-        int_flag: &'a AtomicBool,
-    ) -> Self {
+impl<'a, T: Twi> I2cSlave<'a, T> {
+    pub fn new(twi: T, addr: u8, interrupt: TwiBinding<'a>) -> Self {
         Self {
             twi,
             addr,
-            sda,
-            scl,
-            int_flag,
+            interrupt,
+            pos: 0,
+            wrapped: false,
+            sent: 0,
+            overflow: false,
+            armed: false,
+            written: 0,
+            timeout: None,
+            mask: 0,
+            matched: 0,
+            gca: false,
+            general_call: false,
+            end: End::Stop,
+            padded: false,
+            read_overflow: ReadOverflow::Refuse,
+            write_overflow: WriteOverflow::Abort,
+            dropped: 0,
+            hardware_address: None,
         }
     }
 
     /// Returns the init of this [`I2C_Slave`].
-    pub fn init(&mut self, gca: bool) -> () {
-        // Set slave address
-        self.twi.twar.write(|w| w.twa().bits(self.addr));
+    pub fn init(&mut self, gca: bool) {
+        self.gca = gca;
+
+        // Set slave address, enabling GCA call in the same write
+        self.write_address();
+        self.twi.write_address_mask(self.mask << 1);
+
+        self.twi.reset_control();
+        self.armed = false;
+    }
+
+    fn write_address(&mut self) {
+        let gce = if self.gca { TWGCE } else { 0 };
+
+        self.twi.write_address(self.addr << 1 | gce);
+    }
+
+    /// Handle the general call commands `0x06` (reset and write programmable
+    /// part of slave address) and `0x04` (write programmable part of slave
+    /// address) by taking the own address from `read`, e.g. from address
+    /// select pins. The command is still returned as a general call write.
+    pub fn set_hardware_address(&mut self, read: Option<fn() -> u8>) {
+        self.hardware_address = read;
+    }
+
+    /// Set what is sent once the master reads past the response. An empty
+    /// response is refused with [`ReadOverflow::Wrap`] and
+    /// [`ReadOverflow::RepeatLast`], there is nothing to repeat.
+    pub fn set_read_overflow(&mut self, policy: ReadOverflow) {
+        self.read_overflow = policy;
+    }
+
+    /// Set what happens to written bytes that don't fit the receive buffer
+    pub fn set_write_overflow(&mut self, policy: WriteOverflow) {
+        self.write_overflow = policy;
+    }
+
+    /// Interrupts signalled again before the driver took the previous one,
+    /// since the last call. Nonzero means bus events were handled late.
+    pub fn missed_interrupts(&mut self) -> u8 {
+        self.interrupt.take_missed()
+    }
+
+    /// Written bytes of the last transaction that didn't fit the buffer
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
 
-        // Enable GCA call
-        if gca {
-            self.twi.twar.write(|w| w.twgce().set_bit());
+    /// TWEA for the next written byte
+    fn write_ack(&self, rx: &[u8]) -> u8 {
+        if self.write_overflow == WriteOverflow::Nack && self.pos >= rx.len() {
+            0
+        } else {
+            TWEA
         }
+    }
+
+    /// Next byte for the master, `None` once the response is refused
+    fn next_byte(&mut self, tx: &[u8]) -> Option<u8> {
+        if self.pos >= tx.len() {
+            self.padded = true;
+
+            if self.read_overflow == ReadOverflow::Wrap {
+                self.pos = 0;
+            }
+        }
+
+        let byte = if self.pos < tx.len() {
+            self.pos += 1;
 
-        self.twi.twcr.reset();
+            Some(tx[self.pos - 1])
+        } else {
+            match self.read_overflow {
+                ReadOverflow::Refuse | ReadOverflow::Wrap => None,
+                ReadOverflow::Pad(fill) => Some(fill),
+                ReadOverflow::RepeatLast => tx.last().copied(),
+            }
+        };
 
-        ()
+        if byte.is_some() {
+            self.sent = self.sent.saturating_add(1);
+        }
+
+        byte
+    }
+
+    /// Own slave address
+    pub fn address(&self) -> u8 {
+        self.addr
+    }
+
+    /// Act on a general call command in `data`
+    fn general_call_command(&mut self, data: &[u8]) {
+        let Some(read) = self.hardware_address else {
+            return;
+        };
+
+        match data.first() {
+            Some(&GENERAL_CALL_RESET) | Some(&GENERAL_CALL_ADDRESS) => {}
+            _ => return,
+        }
+
+        self.addr = read() & 0x7F;
+        self.write_address();
+    }
+
+    /// Answer every address that differs from the own address only in the
+    /// bits set in `mask`, e.g. `0x03` for a block of 4 addresses. Takes
+    /// effect with the next [`I2cSlave::init`].
+    pub fn set_address_mask(&mut self, mask: u8) {
+        self.mask = mask & 0x7F;
+    }
+
+    /// Address the master used for the current or last transaction, `0` for a
+    /// general call. Differs from the own address only with an address mask.
+    pub fn matched_address(&self) -> u8 {
+        self.matched
+    }
+
+    /// Latch the address of a pending SLA+R/W, TWDR holds it until TWINT is
+    /// cleared.
+    pub(crate) fn latch_address(&mut self) -> Option<u8> {
+        match self.twi.status() {
+            0x60 | 0x68 | 0x70 | 0x78 | 0xA8 | 0xB0 => {
+                self.matched = self.twi.read_data() >> 1;
+
+                Some(self.matched)
+            }
+            _ => None,
+        }
     }
 
     /// Set TWCR registers enabling TWI to respond [`I2cSlave`].
-    fn arm(&self) -> () {
+    pub(crate) fn arm(&mut self) {
         // Arm TWI
-        self.twi.twcr.write(|w| {
-            w.twsta()
-                .clear_bit()
-                .twsto()
-                .clear_bit()
-                .twea()
-                .set_bit()
-                .twen()
-                .set_bit()
-                .twint()
-                .set_bit()
-                .twie()
-                .set_bit()
-        });
+        self.twi.write_control(TWINT | TWEA | TWEN | TWIE);
+        self.armed = true;
+    }
+
+    /// Arm the TWI unless it's armed already
+    pub(crate) fn arm_once(&mut self) {
+        if !self.armed {
+            self.arm();
+        }
+    }
+
+    /// Status of the pending TWI interrupt
+    pub(crate) fn status(&mut self) -> u8 {
+        self.twi.status()
+    }
+
+    /// Take a byte received with status 0x80/0x90 and ACK the next one
+    pub(crate) fn take_byte(&mut self) -> u8 {
+        let data = self.twi.read_data();
+
+        self.twi.write_control(TWINT | TWEA | TWEN | TWIE);
+
+        data
+    }
+
+    /// Keep TWINT set, stretching SCL, without the interrupt firing again
+    pub(crate) fn hold(&mut self) {
+        self.twi.write_control(TWEA | TWEN);
+    }
+
+    /// Let a held TWINT fire the interrupt again
+    pub(crate) fn unhold(&mut self) {
+        self.twi.write_control(TWEA | TWEN | TWIE);
+    }
+
+    /// Give up waiting for the next TWI interrupt after `polls` checks of the
+    /// interrupt flag, `None` waits forever. A check takes a handful of CPU
+    /// cycles, so the budget scales with the clock.
+    ///
+    /// The budget restarts with every interrupt. It covers the master never
+    /// coming as well as the master disappearing mid-transfer while SCL is
+    /// stretched. On a timeout the bus is released and the TWI is left armed
+    /// for the next address match.
+    pub fn set_timeout(&mut self, polls: Option<u32>) {
+        self.timeout = polls;
+    }
+
+    /// SDA and SCL line levels, high is `true`
+    pub fn lines(&mut self) -> (bool, bool) {
+        (self.twi.sda_high(), self.twi.scl_high())
+    }
+
+    /// The TWI reports a status, which it only does while TWINT is set, so it
+    /// stretches SCL and may drive SDA low. TWSR reads `0xF8` between bus
+    /// events and while the TWI is disabled, so a TWI stuck mid-byte isn't
+    /// detected.
+    pub fn holding_bus(&mut self) -> bool {
+        self.twi.status() != 0xF8
+    }
+
+    /// Turn the TWI off, releasing both lines, and back on if it was armed
+    pub fn recover_bus(&mut self) {
+        self.twi.reset_control();
+        self.pos = 0;
+        self.wrapped = false;
+        self.overflow = false;
+
+        if self.armed {
+            self.arm();
+        }
     }
 
     /// release moved values
-    pub fn split(
-        self,
-    ) -> (
-        TWI,
-        Pin<Input<Floating>, PC4>,
-        Pin<Input<Floating>, PC5>,
-        &'a AtomicBool,
-    ) {
-        (self.twi, self.sda, self.scl, self.int_flag)
+    pub fn split(self) -> (T, TwiBinding<'a>) {
+        (self.twi, self.interrupt)
     }
 
-    pub fn respond(&self, buffer: &[u8]) -> Result<usize, I2CSlaveError> {
-        let mut i: usize = 0;
-        let buffer_len: usize = buffer.len();
-        let mut status: u8;
+    /// Send buffer to the master
+    pub fn respond(&mut self, buffer: &[u8]) -> Result<Summary, I2CSlaveError> {
+        let transfer = self.transfer(Direction::Read, &mut [], buffer)?;
 
-        self.arm();
+        self.summary(Direction::Read, transfer, buffer.len())
+    }
 
-        let result: Result<usize, I2CSlaveError>;
+    /// Receive data and write it to buffer. The summary tells whether it came
+    /// through the own or the general call address.
+    pub fn receive(&mut self, buffer: &mut [u8]) -> Result<Summary, I2CSlaveError> {
+        let transfer = self.transfer(Direction::Write, buffer, &[])?;
 
-        // TODO loop may be reworked into something different
-        result = loop {
-            if self.int_flag.load(Ordering::SeqCst) {
-                // Clearing prescaler bits according to datasheet to read
-                // status codes correctly
-                self.twi.twsr.write(|w| w.twps().bits(0));
+        self.summary(Direction::Write, transfer, buffer.len())
+    }
 
-                status = self.twi.twsr.read().bits();
+    /// Summary of a completed single transfer in the `expect` direction
+    fn summary(
+        &self,
+        expect: Direction,
+        transfer: Transfer,
+        len: usize,
+    ) -> Result<Summary, I2CSlaveError> {
+        let (direction, count) = match transfer {
+            Transfer::Received(count) | Transfer::GeneralCall(count) => (Direction::Write, count),
+            Transfer::Sent(count) => (Direction::Read, count),
+        };
 
-                match status {
-                    // Own SLA+W has been received; ACK has been returned, but we in read mode
-                    0x60 => {
-                        self.twi.twdr.write(|w| w.bits(0));
+        if direction != expect {
+            return Err(I2CSlaveError::NotExpectedTransactionDirection);
+        }
 
-                        // Stop and virtually disconnect
-                        self.twi
-                            .twcr
-                            .write(|w| w.twea().clear_bit().twint().set_bit());
+        Ok(Summary {
+            direction,
+            count,
+            general_call: matches!(transfer, Transfer::GeneralCall(_)),
+            end: self.end,
+            len,
+            dropped: self.dropped,
+        })
+    }
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+    /// Non-blocking [`I2cSlave::respond`] for a superloop. Returns
+    /// `WouldBlock` while the transaction is in flight, the same buffer has to
+    /// be passed until it completes.
+    pub fn try_respond(&mut self, buffer: &[u8]) -> nb::Result<Summary, I2CSlaveError> {
+        let transfer = self.poll_transfer(Direction::Read, &mut [], buffer)?;
 
-                        break Err(I2CSlaveError::NotExpectedTransactionDirection);
-                    }
+        Ok(self.summary(Direction::Read, transfer, buffer.len())?)
+    }
 
-                    // Own SLA+R has been received; ACK has been returned
-                    0xA8 => {
-                        if buffer_len == 0 {
-                            // We have nothing to send
-                            self.twi.twdr.write(|w| w.bits(0x00));
-                            self.twi.twcr.write(|w| {
-                                w.twint()
-                                    .set_bit()
-                                    .twea()
-                                    .clear_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-                        } else {
-                            // Send byte
-                            self.twi.twdr.write(|w| w.bits(buffer[i]));
-
-                            i += 1;
-
-                            self.twi.twcr.write(|w| {
-                                w.twint()
-                                    .set_bit()
-                                    .twea()
-                                    .set_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-                        }
-
-                        self.int_flag.store(false, Ordering::SeqCst);
-                    }
+    /// Non-blocking [`I2cSlave::receive`] for a superloop. Returns
+    /// `WouldBlock` while the transaction is in flight, the same buffer has to
+    /// be passed until it completes.
+    pub fn try_receive(&mut self, buffer: &mut [u8]) -> nb::Result<Summary, I2CSlaveError> {
+        let transfer = self.poll_transfer(Direction::Write, buffer, &[])?;
 
-                    // Arbitration lost in SLA+R/W as Master; own SLA+R has been
-                    // received; ACK has been returned
-                    0xB0 => {
-                        self.twi
-                            .twcr
-                            .write(|w| w.twint().set_bit().twea().clear_bit());
+        Ok(self.summary(Direction::Write, transfer, buffer.len())?)
+    }
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+    /// Serve the next transaction in whichever direction the master starts it.
+    ///
+    /// Writes are stored to `rx`. Reads are answered with the first bytes of
+    /// `tx` as counted by `respond`, which gets called with the data of the
+    /// last write once the master's SLA+R arrives, so a register pointer
+    /// written just before can select the data.
+    ///
+    /// The TWI stays armed after the transaction. That way the SLA+R following
+    /// a write and a repeated START is ACKed right away and the clock is
+    /// stretched until the next call, which must pass the same `rx`. A STOP
+    /// and a repeated START can't be told apart, so a write is returned as soon
+    /// as either of them arrives.
+    pub fn transaction<F>(
+        &mut self,
+        rx: &mut [u8],
+        tx: &mut [u8],
+        respond: F,
+    ) -> Result<Transfer, I2CSlaveError>
+    where
+        F: FnOnce(&[u8], &mut [u8]) -> usize,
+    {
+        self.addressed_transaction(rx, tx, |_, written, tx| respond(written, tx))
+    }
 
-                        break Err(I2CSlaveError::ArbitrationLost);
-                    }
-                    // Data byte in TWDR has been transmitted; ACK has been received
-                    0xB8 => {
-                        if i > buffer_len - 1 {
-                            self.twi.twdr.write(|w| w.bits(0x00));
-
-                            self.twi.twcr.write(|w| {
-                                w.twint()
-                                    .set_bit()
-                                    .twea()
-                                    .clear_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-
-                            break Ok(i);
-                        } else {
-                            self.twi.twdr.write(|w| w.bits(buffer[i]));
-
-                            i += 1;
-
-                            self.twi.twcr.write(|w| {
-                                w.twint()
-                                    .set_bit()
-                                    .twea()
-                                    .set_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-                        }
-
-                        self.int_flag.store(false, Ordering::SeqCst);
-                    }
-                    // Data byte in TWDR has been transmitted; NOT ACK has been received
-                    0xC0 => {
-                        self.twi
-                            .twcr
-                            .write(|w| w.twint().set_bit().twea().clear_bit());
+    /// [`I2cSlave::transaction`] with `respond` getting the matched address
+    pub(crate) fn addressed_transaction<F>(
+        &mut self,
+        rx: &mut [u8],
+        tx: &mut [u8],
+        respond: F,
+    ) -> Result<Transfer, I2CSlaveError>
+    where
+        F: FnOnce(u8, &[u8], &mut [u8]) -> usize,
+    {
+        let mut respond = Some(respond);
+        let mut tx_len = 0;
 
-                        self.int_flag.store(false, Ordering::SeqCst);
+        // Re-arming would release a pending SLA+R with whatever is in TWDR
+        self.arm_once();
 
-                        break Ok(i);
-                    }
-                    // Last data byte in TWDR has been transmitted (TWEA = “0”);
-                    // ACK has been received
-                    0xC8 => {
-                        self.twi
-                            .twcr
-                            .write(|w| w.twint().set_bit().twea().clear_bit());
+        loop {
+            self.wait_listening()?;
 
-                        self.int_flag.store(false, Ordering::SeqCst);
+            match self.poll_transaction(rx, tx, &mut tx_len, &mut respond) {
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(err)) => return Err(err),
+                Ok(transfer) => return Ok(transfer),
+            }
+        }
+    }
 
-                        break Ok(i);
-                    }
-                    0xF8 => {
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+    /// Handle the pending TWI interrupt of [`I2cSlave::transaction`], if there
+    /// is one. `tx_len` and `respond` carry the response between calls.
+    pub(crate) fn poll_transaction<F>(
+        &mut self,
+        rx: &mut [u8],
+        tx: &mut [u8],
+        tx_len: &mut usize,
+        respond: &mut Option<F>,
+    ) -> nb::Result<Transfer, I2CSlaveError>
+    where
+        F: FnOnce(u8, &[u8], &mut [u8]) -> usize,
+    {
+        self.arm_once();
 
-                        // ERROR
-                        break Err(I2CSlaveError::UnknownState(status));
-                    }
-                    _ => {
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+        if !self.take_interrupt() {
+            return Err(nb::Error::WouldBlock);
+        }
 
-                        break Err(I2CSlaveError::UnknownState(status));
-                    }
+        // Own SLA+R has been received, data is needed right now
+        if self.twi.status() == 0xA8 {
+            if let Some(respond) = respond.take() {
+                let address = self.latch_address().unwrap_or(self.matched);
+                let written = self.written.min(rx.len());
+                *tx_len = respond(address, &rx[..written], tx).min(tx.len());
+            }
+        }
+
+        match self.step(None, rx, &tx[..*tx_len]) {
+            Step::Pending => Err(nb::Error::WouldBlock),
+            Step::Done(result) => {
+                match result {
+                    Ok(Transfer::Received(count)) => self.written = count,
+                    // A broadcast isn't a write to this slave, but took its
+                    // place in `rx`
+                    Ok(Transfer::GeneralCall(_)) => self.written = 0,
+                    _ => {}
                 }
+
+                result.map_err(nb::Error::Other)
             }
-        };
+        }
+    }
+
+    /// Run a single transaction in blocking mode and disable the TWI afterwards
+    fn transfer(
+        &mut self,
+        expect: Direction,
+        rx: &mut [u8],
+        tx: &[u8],
+    ) -> Result<Transfer, I2CSlaveError> {
+        // Still armed after a timeout
+        self.arm_once();
+
+        loop {
+            self.wait_listening()?;
+
+            match self.poll_transfer(expect, rx, tx) {
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(err)) => return Err(err),
+                Ok(transfer) => return Ok(transfer),
+            }
+        }
+    }
+
+    /// Handle the pending TWI interrupt of a single transaction, if there is
+    /// one. The TWI is armed on the first call and disabled once done.
+    fn poll_transfer(
+        &mut self,
+        expect: Direction,
+        rx: &mut [u8],
+        tx: &[u8],
+    ) -> nb::Result<Transfer, I2CSlaveError> {
+        self.arm_once();
+
+        if !self.take_interrupt() {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        match self.step(Some(expect), rx, tx) {
+            Step::Pending => Err(nb::Error::WouldBlock),
+            Step::Done(result) => {
+                self.twi.reset_control();
+                self.armed = false;
+
+                result.map_err(nb::Error::Other)
+            }
+        }
+    }
+
+    /// Consume the interrupt flag. It's reset before TWINT is cleared, so the
+    /// next interrupt can't be lost.
+    pub(crate) fn take_interrupt(&mut self) -> bool {
+        self.interrupt.take()
+    }
+
+    /// Wait for the next TWI interrupt within the timeout
+    fn wait(&mut self) -> Result<(), I2CSlaveError> {
+        let mut polls = 0;
+
+        while !self.interrupt.is_pending() {
+            if let Some(timeout) = self.timeout {
+                if polls >= timeout {
+                    return Err(I2CSlaveError::Timeout);
+                }
+
+                polls += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// [`I2cSlave::wait`], releasing the bus and listening for the next address
+    /// match on a timeout
+    pub(crate) fn wait_listening(&mut self) -> Result<(), I2CSlaveError> {
+        let result = self.wait();
+
+        if result.is_err() {
+            self.twi.reset_control();
+            self.arm();
+        }
 
-        self.twi.twcr.reset();
         result
     }
 
-    /// Receive data and write it to buffer
-    pub fn receive(&self, buffer: &mut [u8]) -> Result<(), I2CSlaveError> {
-        let mut i: usize = 0;
-        let buffer_len: usize = buffer.len();
-        let mut status: u8;
+    /// End of transaction: a single expected transfer stops and virtually
+    /// disconnects, otherwise the slave keeps listening for its address.
+    fn finish(
+        &mut self,
+        expect: Option<Direction>,
+        result: Result<Transfer, I2CSlaveError>,
+    ) -> Step {
+        if expect.is_some() {
+            self.twi.write_control(TWINT);
+        } else {
+            self.arm();
+        }
 
-        self.arm();
+        Step::Done(result)
+    }
 
-        let result: Result<(), I2CSlaveError>;
+    /// Recover from a bus error: setting TWSTO and clearing TWINT switches to
+    /// not addressed slave mode and releases SDA and SCL without sending a
+    /// STOP. The slave keeps listening unless a single transfer was expected.
+    fn recover(&mut self, expect: Option<Direction>) {
+        self.pos = 0;
+        self.wrapped = false;
+        self.overflow = false;
+
+        if expect.is_some() {
+            self.twi.write_control(TWINT | TWSTO | TWEN);
+        } else {
+            self.twi.write_control(TWINT | TWSTO | TWEA | TWEN | TWIE);
+            self.armed = true;
+        }
+    }
 
-        // Read I2C in blocking mode
-        result = loop {
-            if self.int_flag.load(Ordering::SeqCst) {
-                // Clearing prescaler bits according to datasheet to read
-                // status codes correctly
-                self.twi.twsr.write(|w| w.twps().bits(0));
+    /// Handle one TWI interrupt. `expect` restricts the transaction to one
+    /// direction, with `None` both directions are served and the slave stays
+    /// armed after the transaction.
+    pub(crate) fn step(&mut self, expect: Option<Direction>, rx: &mut [u8], tx: &[u8]) -> Step {
+        let status = self.twi.status();
 
-                status = self.twi.twsr.read().bits();
+        self.latch_address();
 
-                match status {
-                    // READ mode is not expected
-                    0xA8 => {
-                        self.twi.twdr.write(|w| w.bits(0));
+        match status {
+            // Own SLA+W has been received; ACK has been returned, but we in read mode
+            // General call address has been received; ACK has been returned
+            0x60 | 0x70 if expect == Some(Direction::Read) => {
+                self.twi.write_data(0);
 
-                        // Stop and virtually disconnect
-                        self.twi
-                            .twcr
-                            .write(|w| w.twea().clear_bit().twint().set_bit());
+                // Stop and virtually disconnect
+                self.twi.write_control(TWINT);
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+                Step::Done(Err(I2CSlaveError::NotExpectedTransactionDirection))
+            }
 
-                        break Err(I2CSlaveError::NotExpectedTransactionDirection);
-                    }
+            // READ mode is not expected
+            0xA8 if expect == Some(Direction::Write) => {
+                self.twi.write_data(0);
 
-                    // Own SLA+W has been received; ACK has been returned
-                    0x60 => {
-                        // Continue, wait for data
-                        self.twi.twcr.write(|w| {
-                            w.twint()
-                                .set_bit()
-                                .twea()
-                                .set_bit()
-                                .twie()
-                                .set_bit()
-                                .twen()
-                                .set_bit()
-                        });
-
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
-                    }
+                // Stop and virtually disconnect
+                self.twi.write_control(TWINT);
 
-                    // Arbitration lost in SLA+R/W as Master; own SLA+W has been
-                    // received; ACK has been returned
-                    0x68 => {
-                        // Data byte will be received and NOT ACK will be returned
-                        self.twi
-                            .twcr
-                            .write(|w| w.twint().set_bit().twea().clear_bit());
+                Step::Done(Err(I2CSlaveError::NotExpectedTransactionDirection))
+            }
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+            // Own SLA+W has been received; ACK has been returned
+            // General call address has been received; ACK has been returned
+            0x60 | 0x70 => {
+                self.pos = 0;
+                self.wrapped = false;
+                self.overflow = false;
+                self.dropped = 0;
+                self.general_call = status == 0x70;
 
-                        break Err(I2CSlaveError::ArbitrationLost);
-                    }
+                // Continue, wait for data
+                let ack = self.write_ack(rx);
+                self.twi.write_control(TWINT | ack | TWEN | TWIE);
 
-                    // General call address has been received; ACK has been returned
-                    0x70 => {
-                        // Continue, wait for data
-                        self.twi.twcr.write(|w| {
-                            w.twint()
-                                .set_bit()
-                                .twea()
-                                .set_bit()
-                                .twie()
-                                .set_bit()
-                                .twen()
-                                .set_bit()
-                        });
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
-                    }
+                Step::Pending
+            }
 
-                    // Arbitration lost in SLA+R/W as Master; General call
-                    // address has been received; ACK has been returned
-                    0x78 => {
-                        // Data byte will be received and NOT ACK will be returned
-                        self.twi
-                            .twcr
-                            .write(|w| w.twint().set_bit().twea().clear_bit());
+            // Arbitration lost in SLA+R/W as Master; own SLA+W has been
+            // received; ACK has been returned
+            // Arbitration lost in SLA+R/W as Master; General call
+            // address has been received; ACK has been returned
+            // Arbitration lost in SLA+R/W as Master; own SLA+R has been
+            // received; ACK has been returned
+            0x68 | 0x78 | 0xB0 => {
+                // Data byte will be received and NOT ACK will be returned
+                self.finish(expect, Err(I2CSlaveError::ArbitrationLost))
+            }
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+            // Previously addressed with own SLA+W; data has been received;
+            // ACK has been returned
+            // Previously addressed with general call; data has been
+            // received; ACK has been returned
+            0x80 | 0x90 if expect != Some(Direction::Read) => {
+                let full = self.pos >= rx.len();
 
-                        break Err(I2CSlaveError::ArbitrationLost);
-                    }
+                if full && self.write_overflow == WriteOverflow::Abort {
+                    // Byte has been ACKed already
+                    self.dropped = self.dropped.saturating_add(1);
 
-                    // Previously addressed with own SLA+W; data has been received;
-                    // ACK has been returned
-                    0x80 => {
-                        if i > buffer_len - 1 {
-                            // Stop and virtually disconnect
-                            self.twi
-                                .twcr
-                                .write(|w| w.twea().clear_bit().twint().set_bit());
-
-                            // Resetting flag
-                            self.int_flag.store(false, Ordering::SeqCst);
-
-                            break Err(I2CSlaveError::BufferOverflow);
-                        } else {
-                            // Write data to buffer
-                            buffer[i] = self.twi.twdr.read().bits();
-
-                            i += 1;
-
-                            // Wait for more
-                            self.twi.twcr.write(|w| {
-                                w.twea()
-                                    .set_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twint()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-
-                            // Resetting flag
-                            self.int_flag.store(false, Ordering::SeqCst);
-                        }
-                    }
-                    0x88 => {
+                    if expect.is_some() {
                         // Stop and virtually disconnect
-                        self.twi
-                            .twcr
-                            .write(|w| w.twea().clear_bit().twint().set_bit());
-
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+                        self.twi.write_control(TWINT);
 
-                        break Ok(());
+                        return Step::Done(Err(I2CSlaveError::BufferOverflow));
                     }
-                    // Previously addressed with general call; data has been
-                    // received; ACK has been returned
-                    0x90 => {
-                        if i > buffer_len - 1 {
-                            // Stop and virtually disconnect
-                            self.twi
-                                .twcr
-                                .write(|w| w.twea().clear_bit().twint().set_bit());
-
-                            // Resetting flag
-                            self.int_flag.store(false, Ordering::SeqCst);
-
-                            break Err(I2CSlaveError::BufferOverflow);
-                        } else {
-                            // Write data to buffer
-                            buffer[i] = self.twi.twdr.read().bits();
-
-                            i += 1;
-
-                            // Wait for more
-                            self.twi.twcr.write(|w| {
-                                w.twea()
-                                    .set_bit()
-                                    .twen()
-                                    .set_bit()
-                                    .twint()
-                                    .set_bit()
-                                    .twie()
-                                    .set_bit()
-                            });
-
-                            // Resetting flag
-                            self.int_flag.store(false, Ordering::SeqCst);
-                        }
-                    }
-                    0x98 => {
-                        // Stop and virtually disconnect
-                        self.twi
-                            .twcr
-                            .write(|w| w.twea().clear_bit().twint().set_bit());
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+                    // Drop the rest of the transaction, NOT ACK the next byte
+                    self.overflow = true;
+                    self.twi.write_control(TWINT | TWEN | TWIE);
 
-                        break Ok(());
-                    }
-                    // A STOP condition or repeated START condition has been
-                    // received while still addressed as Slave
-                    0xA0 => {
-                        // Stop and virtually disconnect
-                        self.twi
-                            .twcr
-                            .write(|w| w.twea().clear_bit().twint().set_bit());
+                    return Step::Pending;
+                }
 
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+                let data = self.twi.read_data();
 
-                        break Ok(());
-                    }
-                    0xf8 => {
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
+                if full && self.write_overflow == WriteOverflow::KeepNewest {
+                    self.pos = 0;
+                    self.wrapped = true;
+                }
 
-                        // ERROR
-                        break Err(I2CSlaveError::UnknownState(status));
+                if self.pos < rx.len() {
+                    if self.wrapped {
+                        // Oldest byte is overwritten
+                        self.dropped = self.dropped.saturating_add(1);
                     }
-                    _ => {
-                        // Resetting flag
-                        self.int_flag.store(false, Ordering::SeqCst);
 
-                        break Err(I2CSlaveError::UnknownState(status));
+                    // Write data to buffer
+                    rx[self.pos] = data;
+                    self.pos += 1;
+                } else {
+                    self.dropped = self.dropped.saturating_add(1);
+                }
+
+                // Wait for more
+                let ack = self.write_ack(rx);
+                self.twi.write_control(TWINT | ack | TWEN | TWIE);
+
+                Step::Pending
+            }
+
+            // Previously addressed with own SLA+W; data has been received;
+            // NOT ACK has been returned
+            // Previously addressed with general call; data has been
+            // received; NOT ACK has been returned
+            // A STOP condition or repeated START condition has been
+            // received while still addressed as Slave
+            0x88 | 0x98 | 0xA0 if expect != Some(Direction::Read) => {
+                self.end = if status == 0xA0 {
+                    End::Stop
+                } else {
+                    // NACKed byte isn't stored
+                    self.dropped = self.dropped.saturating_add(1);
+
+                    End::Nacked
+                };
+
+                // Oldest byte of the ring to the front
+                if self.wrapped {
+                    rx.rotate_left(self.pos);
+                    self.pos = rx.len();
+                    self.wrapped = false;
+                }
+
+                let result = if self.overflow {
+                    Err(I2CSlaveError::BufferOverflow)
+                } else if self.general_call {
+                    self.general_call_command(&rx[..self.pos]);
+
+                    Ok(Transfer::GeneralCall(self.pos))
+                } else {
+                    Ok(Transfer::Received(self.pos))
+                };
+
+                self.finish(expect, result)
+            }
+
+            // Own SLA+R has been received; ACK has been returned
+            0xA8 => {
+                self.pos = 0;
+                self.sent = 0;
+                self.padded = false;
+                self.dropped = 0;
+
+                if let Some(byte) = self.next_byte(tx) {
+                    // Send byte
+                    self.twi.write_data(byte);
+                    self.twi.write_control(TWINT | TWEA | TWEN | TWIE);
+                } else {
+                    // We have nothing to send
+                    self.twi.write_data(0x00);
+                    self.twi.write_control(TWINT | TWEN | TWIE);
+                }
+
+                Step::Pending
+            }
+
+            // Data byte in TWDR has been transmitted; ACK has been received
+            0xB8 if expect != Some(Direction::Write) => {
+                if let Some(byte) = self.next_byte(tx) {
+                    self.twi.write_data(byte);
+                    self.twi.write_control(TWINT | TWEA | TWEN | TWIE);
+                } else {
+                    self.twi.write_data(0x00);
+                    self.twi.write_control(TWINT | TWEN | TWIE);
+
+                    if expect.is_some() {
+                        self.end = End::ReadPastEnd;
+
+                        return Step::Done(Ok(Transfer::Sent(self.sent)));
                     }
                 }
+
+                Step::Pending
             }
-        };
 
-        self.twi.twcr.reset();
+            // Data byte in TWDR has been transmitted; NOT ACK has been received
+            // Last data byte in TWDR has been transmitted (TWEA = “0”);
+            // ACK has been received
+            0xC0 | 0xC8 if expect != Some(Direction::Write) => {
+                let sent = self.sent;
 
-        result
+                self.end = if status == 0xC0 && !self.padded {
+                    End::MasterNack
+                } else {
+                    End::ReadPastEnd
+                };
+
+                self.finish(expect, Ok(Transfer::Sent(sent)))
+            }
+
+            // Bus error due to an illegal START or STOP condition
+            0x00 => {
+                self.recover(expect);
+
+                Step::Done(Err(I2CSlaveError::BusError))
+            }
+
+            _ => {
+                if expect.is_none() {
+                    // Keep listening
+                    self.arm();
+                }
+
+                Step::Done(Err(I2CSlaveError::UnknownState(status)))
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::{bus_sim::ADDR, twi_interrupt::TwiInterrupt, twi_mock::MockTwi};
 
-    fn slice_size(b: &mut [u8]) -> usize {
+    fn slice_size(b: &[u8]) -> usize {
         b.len()
     }
 
     #[test]
     fn sample_tests() {
-        let mut buffer: [u8; 4] = [0; 4];
+        let buffer: [u8; 4] = [0; 4];
 
         assert_eq!(slice_size(&buffer), 4);
     }
+
+    #[test]
+    fn init_writes_slave_address() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &[]), ADDR, flag.bind().unwrap());
+
+        slave.init(false);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.twar, ADDR << 1);
+        assert_eq!(twi.resets, 1);
+    }
+
+    #[test]
+    fn init_keeps_address_with_general_call() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &[]), ADDR, flag.bind().unwrap());
+
+        slave.init(true);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.twar, ADDR << 1 | TWGCE);
+    }
+
+    #[test]
+    fn init_writes_address_mask() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &[]), ADDR, flag.bind().unwrap());
+
+        slave.set_address_mask(0x03);
+        slave.init(false);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.twamr, 0x03 << 1);
+    }
+
+    #[test]
+    fn receive_fills_buffer_until_stop() {
+        let flag = TwiInterrupt::new();
+        let script = [(0x60, 0), (0x80, 1), (0x80, 2), (0x80, 3), (0xA0, 0)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+        let mut buf = [0; 4];
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary {
+                direction: Direction::Write,
+                count: 3,
+                end: End::Stop,
+                ..
+            })
+        ));
+        assert_eq!(buf, [1, 2, 3, 0]);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.remaining(), 0);
+        assert_eq!(twi.resets, 1);
+    }
+
+    #[test]
+    fn receive_overflow() {
+        let flag = TwiInterrupt::new();
+        let script = [(0x60, 0), (0x80, 1), (0x80, 2), (0x80, 3)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+        let mut buf = [0; 2];
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::BufferOverflow)
+        ));
+        assert_eq!(buf, [1, 2]);
+    }
+
+    #[test]
+    fn receive_rejects_read_request() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(
+            MockTwi::new(&flag, &[(0xA8, 0)]),
+            ADDR,
+            flag.bind().unwrap(),
+        );
+        let mut buf = [0; 4];
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::NotExpectedTransactionDirection)
+        ));
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.control.last(), Some(&TWINT));
+    }
+
+    #[test]
+    fn receive_unknown_state() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(
+            MockTwi::new(&flag, &[(0x08, 0)]),
+            ADDR,
+            flag.bind().unwrap(),
+        );
+        let mut buf = [0; 4];
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::UnknownState(0x08))
+        ));
+    }
+
+    #[test]
+    fn bus_error_recovers() {
+        let flag = TwiInterrupt::new();
+        let script = [(0x60, 0), (0x00, 0), (0x60, 0), (0x80, 7), (0xA0, 0)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+        let mut rx = [0; 2];
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Err(I2CSlaveError::BusError)
+        ));
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Ok(Transfer::Received(1))
+        ));
+        assert_eq!(rx[0], 7);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.control[2], TWINT | TWSTO | TWEA | TWEN | TWIE);
+    }
+
+    #[test]
+    fn respond_sends_buffer_until_nack() {
+        let flag = TwiInterrupt::new();
+        let script = [(0xA8, 0), (0xB8, 0), (0xB8, 0), (0xC0, 0)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+
+        assert!(matches!(
+            slave.respond(&[10, 20, 30]),
+            Ok(Summary {
+                direction: Direction::Read,
+                count: 3,
+                end: End::MasterNack,
+                ..
+            })
+        ));
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.transmitted, [10, 20, 30]);
+        assert_eq!(twi.resets, 1);
+    }
+
+    #[test]
+    fn receive_times_out_mid_transfer() {
+        let flag = TwiInterrupt::new();
+        let script = [(0x60, 0), (0x80, 1)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+        let mut buf = [0; 4];
+
+        slave.set_timeout(Some(100));
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::Timeout)
+        ));
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.remaining(), 0);
+        assert_eq!(twi.resets, 1);
+    }
+
+    #[test]
+    fn receive_after_timeout() {
+        let flag = TwiInterrupt::new();
+        let script = [(0x60, 0), (0x80, 1)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+        let mut buf = [0; 4];
+
+        slave.set_timeout(Some(100));
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Err(I2CSlaveError::Timeout)
+        ));
+        assert_eq!(
+            slave.twi.control.last(),
+            Some(&(TWINT | TWEA | TWEN | TWIE))
+        );
+
+        // Master starts over, the TWI is still listening
+        slave.twi.start(&[(0x60, 0), (0x80, 5), (0xA0, 0)]);
+
+        assert!(matches!(
+            slave.receive(&mut buf),
+            Ok(Summary { count: 1, .. })
+        ));
+        assert_eq!(buf[0], 5);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.remaining(), 0);
+    }
+
+    #[test]
+    fn transaction_times_out_and_stays_armed() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &[]), ADDR, flag.bind().unwrap());
+        let mut rx = [0; 4];
+
+        slave.set_timeout(Some(100));
+
+        assert!(matches!(
+            slave.transaction(&mut rx, &mut [], |_, _| 0),
+            Err(I2CSlaveError::Timeout)
+        ));
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.resets, 1);
+        assert_eq!(twi.control.last(), Some(&(TWINT | TWEA | TWEN | TWIE)));
+    }
+
+    #[test]
+    fn try_receive_would_block_until_stop() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &[]), ADDR, flag.bind().unwrap());
+        let mut buf = [0; 4];
+
+        // Nothing on the bus, armed only once
+        assert!(matches!(
+            slave.try_receive(&mut buf),
+            Err(nb::Error::WouldBlock)
+        ));
+        assert!(matches!(
+            slave.try_receive(&mut buf),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        let (twi, interrupt) = slave.split();
+        assert_eq!(twi.control, [TWINT | TWEA | TWEN | TWIE]);
+
+        let script = [(0x60, 0), (0x80, 1), (0x80, 2), (0xA0, 0)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, interrupt);
+        let mut polls = 0;
+
+        let result = loop {
+            polls += 1;
+
+            match slave.try_receive(&mut buf) {
+                Err(nb::Error::WouldBlock) => continue,
+                result => break result,
+            }
+        };
+
+        assert!(result.is_ok());
+        assert_eq!(polls, 4);
+        assert_eq!(buf[..2], [1, 2]);
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.resets, 1);
+    }
+
+    #[test]
+    fn try_respond_sends_buffer() {
+        let flag = TwiInterrupt::new();
+        let script = [(0xA8, 0), (0xB8, 0), (0xC0, 0)];
+        let mut slave = I2cSlave::new(MockTwi::new(&flag, &script), ADDR, flag.bind().unwrap());
+
+        assert!(matches!(
+            nb::block!(slave.try_respond(&[7, 8, 9])),
+            Ok(summary) if summary.count == 2 && summary.short_read()
+        ));
+
+        let (twi, _) = slave.split();
+        assert_eq!(twi.transmitted, [7, 8]);
+    }
+
+    #[test]
+    fn respond_rejects_write_request() {
+        let flag = TwiInterrupt::new();
+        let mut slave = I2cSlave::new(
+            MockTwi::new(&flag, &[(0x60, 0)]),
+            ADDR,
+            flag.bind().unwrap(),
+        );
+
+        assert!(matches!(
+            slave.respond(&[1]),
+            Err(I2CSlaveError::NotExpectedTransactionDirection)
+        ));
+    }
 }
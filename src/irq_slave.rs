@@ -0,0 +1,596 @@
+//! Interrupt driven slave.
+//!
+//! [`IrqSlave`] runs the slave state machine from the TWI interrupt handler on
+//! buffers it owns, so the main loop is free between transactions and only
+//! picks up completed ones with [`IrqSlave::take_event`].
+//!
+//! A transaction directly following a write is held, stretching the clock,
+//! until the write has been taken, so the received data isn't overwritten.
+//! Taking it and updating the response in the same critical section answers a
+//! write-then-read with data based on the write.
+//!
+//! With a [`Stretch`] policy SCL is also held after the address or after every
+//! byte until the application calls [`IrqSlave::resume`], bounded by
+//! [`IrqSlave::set_max_stretch`] ticks.
+//!
+//! The driver is shared with the ISR through a static, e.g.
+//!
+//! ```ignore
+//! static SLAVE: Mutex<RefCell<Option<IrqSlave<AvrTwi, 4>>>> =
+//!     Mutex::new(RefCell::new(None));
+//!
+//! bind_twi_interrupt!(TWI_INTERRUPT, || avr_device::interrupt::free(|cs| {
+//!     if let Some(slave) = SLAVE.borrow(cs).borrow_mut().as_mut() {
+//!         slave.on_interrupt();
+//!     }
+//! }));
+//! ```
+
+use crate::{
+    bus_monitor::{BusHealth, BusMonitor},
+    i2c_slave::{I2CSlaveError, I2cSlave, Step, Transfer},
+    snapshot::TxSnapshot,
+    twi::Twi,
+};
+
+/// Points at which the interrupt handler holds SCL low
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stretch {
+    /// Only a transaction following an untaken write is held
+    Never,
+    /// After the own SLA+R/W or the general call address
+    Address,
+    /// After the address and before every data byte
+    Byte,
+}
+
+/// Reason SCL is held
+#[derive(Clone, Copy, PartialEq)]
+enum Held {
+    /// Transaction following a write which wasn't taken yet
+    Write,
+    /// Requested by the stretch policy
+    Stretch,
+}
+
+pub struct IrqSlave<'a, T: Twi, const N: usize> {
+    slave: I2cSlave<'a, T>,
+    rx: [u8; N],
+    rx_len: usize,
+    tx: [u8; N],
+    tx_len: usize,
+    // Responses come from here instead of `tx`
+    snapshot: Option<&'a TxSnapshot<N>>,
+    event: Option<Result<Transfer, I2CSlaveError>>,
+    held: Option<Held>,
+    stretch: Stretch,
+    // Next interrupt was already held and is handled right away
+    resumed: bool,
+    max_stretch: Option<u32>,
+    stretch_ticks: u32,
+}
+
+impl<'a, T: Twi, const N: usize> IrqSlave<'a, T, N> {
+    /// Wrap an initialized [`I2cSlave`]. The TWI interrupt handler calls
+    /// [`IrqSlave::on_interrupt`], which takes the signalled interrupt.
+    pub fn new(slave: I2cSlave<'a, T>) -> Self {
+        Self {
+            slave,
+            rx: [0; N],
+            rx_len: 0,
+            tx: [0; N],
+            tx_len: 0,
+            snapshot: None,
+            event: None,
+            held: None,
+            stretch: Stretch::Never,
+            resumed: false,
+            max_stretch: None,
+            stretch_ticks: 0,
+        }
+    }
+
+    /// Hold SCL at the points of `stretch` until [`IrqSlave::resume`]
+    pub fn set_stretch(&mut self, stretch: Stretch) {
+        self.stretch = stretch;
+    }
+
+    /// Release SCL on its own after `ticks` calls of [`IrqSlave::tick`],
+    /// `None` holds it until released.
+    pub fn set_max_stretch(&mut self, ticks: Option<u32>) {
+        self.max_stretch = ticks;
+    }
+
+    /// SCL is held, waiting for the application
+    pub fn stretching(&self) -> bool {
+        self.held.is_some()
+    }
+
+    /// Release held SCL and let the transaction continue
+    pub fn resume(&mut self) {
+        if self.held.take().is_some() {
+            self.resumed = true;
+            self.slave.unhold();
+        }
+    }
+
+    /// Time base of the maximum stretch, e.g. called from a timer interrupt.
+    /// Running out of it resumes and reports [`I2CSlaveError::Timeout`]
+    /// unless an event is waiting already.
+    pub fn tick(&mut self) {
+        if self.held.is_none() {
+            return;
+        }
+
+        self.stretch_ticks += 1;
+
+        if self
+            .max_stretch
+            .is_some_and(|max| self.stretch_ticks >= max)
+        {
+            if self.event.is_none() {
+                self.event = Some(Err(I2CSlaveError::Timeout));
+            }
+
+            self.resume();
+        }
+    }
+
+    fn hold(&mut self, held: Held) {
+        self.slave.hold();
+        self.held = Some(held);
+        self.stretch_ticks = 0;
+    }
+
+    /// Status is one the stretch policy holds at
+    fn stretches(&self, status: u8) -> bool {
+        match self.stretch {
+            Stretch::Never => false,
+            Stretch::Address => matches!(status, 0x60 | 0x70 | 0xA8),
+            Stretch::Byte => matches!(status, 0x60 | 0x70 | 0xA8 | 0x80 | 0x90 | 0xB8),
+        }
+    }
+
+    /// Arm the TWI, from now on transactions in both directions are served
+    /// from the interrupt handler.
+    pub fn listen(&mut self) {
+        self.slave.arm();
+    }
+
+    /// Handle the TWI interrupt.
+    pub fn on_interrupt(&mut self) {
+        self.slave.take_interrupt();
+
+        if self.resumed {
+            self.resumed = false;
+        } else {
+            let status = self.slave.status();
+
+            // Addressed again, but the preceding write wasn't taken
+            let written =
+                matches!(&self.event, Some(Ok(transfer)) if transfer.received().is_some());
+
+            if written && matches!(status, 0x60 | 0x70 | 0xA8) {
+                self.hold(Held::Write);
+                return;
+            }
+
+            if self.stretches(status) {
+                self.hold(Held::Stretch);
+                return;
+            }
+        }
+
+        let tx = match self.snapshot {
+            Some(snapshot) => {
+                // Own SLA+R has been received
+                if self.slave.status() == 0xA8 {
+                    snapshot.latch();
+                }
+
+                snapshot.latched()
+            }
+            None => &self.tx[..self.tx_len],
+        };
+
+        if let Step::Done(result) = self.slave.step(None, &mut self.rx, tx) {
+            if let Some(snapshot) = self.snapshot {
+                snapshot.unlatch();
+            }
+
+            if let Some(count) = result.as_ref().ok().and_then(Transfer::received) {
+                self.rx_len = count;
+            }
+
+            self.event = Some(result);
+        }
+    }
+
+    /// Last completed transaction, if it wasn't taken yet. A transaction held
+    /// for it is resumed.
+    pub fn take_event(&mut self) -> Option<Result<Transfer, I2CSlaveError>> {
+        if self.held == Some(Held::Write) {
+            self.resume();
+        }
+
+        self.event.take()
+    }
+
+    /// Data of the last completed receive. It's overwritten by the next
+    /// write transaction, which is held until the event has been taken.
+    pub fn received(&self) -> &[u8] {
+        &self.rx[..self.rx_len]
+    }
+
+    /// Written bytes of the last transaction that didn't fit the buffer
+    pub fn dropped(&self) -> usize {
+        self.slave.dropped()
+    }
+
+    /// Address the master used for the current or last transaction
+    pub fn matched_address(&self) -> u8 {
+        self.slave.matched_address()
+    }
+
+    /// Data sent to the master on the following read transactions, truncated
+    /// to the buffer size.
+    pub fn set_response(&mut self, data: &[u8]) {
+        let len = data.len().min(N);
+
+        self.tx[..len].copy_from_slice(&data[..len]);
+        self.tx_len = len;
+    }
+
+    /// Answer reads with the snapshot published last, see [`TxSnapshot`].
+    /// Replaces the data of [`IrqSlave::set_response`].
+    pub fn respond_from(&mut self, snapshot: &'a TxSnapshot<N>) {
+        self.snapshot = Some(snapshot);
+    }
+
+    /// Sample the bus lines with `monitor`
+    pub fn check_bus(&mut self, monitor: &mut BusMonitor) -> BusHealth {
+        monitor.sample(&mut self.slave)
+    }
+
+    /// Free stuck lines, see [`I2cSlave::recover_bus`]. A stretched clock is
+    /// released as well.
+    pub fn recover_bus(&mut self) {
+        self.held = None;
+        self.resumed = false;
+        self.slave.recover_bus();
+
+        // Read in progress is aborted
+        if let Some(snapshot) = self.snapshot {
+            snapshot.unlatch();
+        }
+    }
+
+    /// release moved values
+    pub fn release(self) -> I2cSlave<'a, T> {
+        self.slave
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IrqSlave, Stretch};
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::{I2CSlaveError, I2cSlave, Transfer},
+        twi_interrupt::TwiInterrupt,
+    };
+
+    /// Run the interrupt handler until a transaction completes
+    fn service<const N: usize>(
+        flag: &TwiInterrupt,
+        slave: &mut IrqSlave<'_, SimBus<'_>, N>,
+    ) -> Option<Result<Transfer, I2CSlaveError>> {
+        while flag.is_pending() {
+            slave.on_interrupt();
+
+            if let Some(event) = slave.take_event() {
+                return Some(event);
+            }
+        }
+
+        None
+    }
+
+    fn listening<const N: usize>(bus: SimBus<'_>) -> IrqSlave<'_, SimBus<'_>, N> {
+        let mut slave = IrqSlave::new(sim_slave(bus));
+        slave.listen();
+        slave
+    }
+
+    #[test]
+    fn receive_then_respond() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3])
+            .stop()
+            .read(ADDR, 3)
+            .stop();
+        let mut slave = listening::<4>(bus);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Received(3)))
+        ));
+        assert_eq!(slave.received(), [1, 2, 3]);
+
+        slave.set_response(&[10, 20, 30]);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Sent(3)))
+        ));
+        assert!(service(&flag, &mut slave).is_none());
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [10, 20, 30]);
+    }
+
+    #[test]
+    fn stays_armed_between_transactions() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1])
+            .stop()
+            .write(ADDR, &[2, 3])
+            .stop();
+        let mut slave = listening::<4>(bus);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Received(2)))
+        ));
+        assert_eq!(slave.received(), [2, 3]);
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.address_acks, [true, true]);
+    }
+
+    #[test]
+    fn overflow_nacks_rest_of_transaction() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3, 4])
+            .stop()
+            .write(ADDR, &[5])
+            .stop();
+        let mut slave = listening::<2>(bus);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Err(I2CSlaveError::BufferOverflow))
+        ));
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        assert_eq!(slave.received(), [5]);
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.acks, [true, true, true, false, true]);
+    }
+
+    #[test]
+    fn read_is_held_until_write_is_taken() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1]).read(ADDR, 2).stop();
+        let mut slave = listening::<4>(bus);
+
+        // Main loop is late, both the write and the SLA+R are handled first
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+
+        assert!(matches!(
+            slave.take_event(),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        slave.set_response(&[slave.received()[0] * 10, 0]);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Sent(2)))
+        ));
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [10, 0]);
+    }
+
+    #[test]
+    fn write_is_held_until_previous_write_is_taken() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2])
+            .stop()
+            .write(ADDR, &[3])
+            .stop();
+        let mut slave = listening::<4>(bus);
+
+        // Main loop is late, the second SLA+W arrives before the first write
+        // is taken
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+
+        assert!(slave.stretching());
+        assert!(matches!(
+            slave.take_event(),
+            Some(Ok(Transfer::Received(2)))
+        ));
+        assert_eq!(slave.received(), [1, 2]);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        assert_eq!(slave.received(), [3]);
+
+        finished(slave.release());
+    }
+
+    #[test]
+    fn stretch_after_address_until_resumed() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 2).stop();
+        let mut slave = listening::<2>(bus);
+
+        slave.set_stretch(Stretch::Address);
+
+        assert!(service(&flag, &mut slave).is_none());
+        assert!(slave.stretching());
+
+        // Response computed while the master waits
+        slave.set_response(&[4, 2]);
+        slave.resume();
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Sent(2)))
+        ));
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [4, 2]);
+    }
+
+    #[test]
+    fn taking_write_resumes_stretched_read() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1]).read(ADDR, 2).stop();
+        let mut slave = listening::<2>(bus);
+
+        slave.set_stretch(Stretch::Address);
+
+        // SLA+W
+        assert!(service(&flag, &mut slave).is_none());
+        slave.resume();
+
+        // Write ends with the SLA+R, which is held for the write
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+        assert!(matches!(
+            slave.take_event(),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        slave.set_response(&[4, 2]);
+
+        // Taking the write resumed the read, it isn't stretched again
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Sent(2)))
+        ));
+        assert!(!slave.stretching());
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [4, 2]);
+    }
+
+    #[test]
+    fn max_stretch_releases_bus() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).write(ADDR, &[1, 2]).stop();
+        let mut slave = listening::<2>(bus);
+
+        slave.set_stretch(Stretch::Byte);
+        slave.set_max_stretch(Some(2));
+
+        let mut timeouts = 0;
+        let event = loop {
+            if let Some(event) = service(&flag, &mut slave) {
+                if !matches!(event, Err(I2CSlaveError::Timeout)) {
+                    break event;
+                }
+                timeouts += 1;
+            }
+
+            slave.tick();
+        };
+
+        // Address and both bytes
+        assert_eq!(timeouts, 3);
+        assert!(matches!(event, Ok(Transfer::Received(2))));
+
+        finished(slave.release());
+    }
+
+    #[test]
+    fn empty_response() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 2).stop();
+        let mut slave = listening::<2>(bus);
+
+        assert!(matches!(
+            service(&flag, &mut slave),
+            Some(Ok(Transfer::Sent(0)))
+        ));
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [0x00, 0xFF]);
+    }
+
+    /// Run one interrupt, echoing what was written back to the next read
+    fn echo<const N: usize>(slave: &mut IrqSlave<'_, SimBus<'_>, N>, sent: &mut usize) {
+        slave.on_interrupt();
+
+        match slave.take_event() {
+            Some(Ok(Transfer::Received(_))) => {
+                let mut response = [0; N];
+                let received = slave.received();
+                response[..received.len()].copy_from_slice(received);
+
+                slave.set_response(&response[..received.len()]);
+            }
+            Some(Ok(Transfer::Sent(count))) => *sent += count,
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn two_buses_run_concurrently() {
+        // Like TWI0 and TWI1 of the atmega328pb, each with its own interrupt
+        let flag0 = TwiInterrupt::new();
+        let flag1 = TwiInterrupt::new();
+        let bus0 = SimBus::new(&flag0)
+            .write(ADDR, &[1, 2])
+            .stop()
+            .read(ADDR, 2)
+            .stop();
+        let bus1 = SimBus::new(&flag1)
+            .write(ADDR + 1, &[3, 4, 5])
+            .stop()
+            .read(ADDR + 1, 3)
+            .stop();
+
+        let mut slave0 = listening::<4>(bus0);
+        let mut slave1 = I2cSlave::new(bus1, ADDR + 1, flag1.bind().unwrap());
+        slave1.init(false);
+        let mut slave1 = IrqSlave::<_, 4>::new(slave1);
+        slave1.listen();
+
+        // Interrupts of both buses interleave
+        let (mut sent0, mut sent1) = (0, 0);
+        while flag0.is_pending() || flag1.is_pending() {
+            if flag0.is_pending() {
+                echo(&mut slave0, &mut sent0);
+            }
+            if flag1.is_pending() {
+                echo(&mut slave1, &mut sent1);
+            }
+        }
+
+        assert_eq!((sent0, sent1), (2, 3));
+
+        let bus0 = finished(slave0.release());
+        let bus1 = finished(slave1.release());
+        assert_eq!(bus0.read, [1, 2]);
+        assert_eq!(bus1.read, [3, 4, 5]);
+    }
+}
@@ -0,0 +1,76 @@
+//! I2C slave driver for the TWI of AVR microcontrollers.
+//!
+//! [`I2cSlave`] runs the slave state machine on top of the [`Twi`] registers,
+//! either blocking, polled or async. [`irq_slave`], [`handler`] and
+//! [`rx_queue`] serve it from the TWI interrupt instead. The interrupt is
+//! installed with [`bind_twi_interrupt!`]. The attiny85 emulates the TWI on its
+//! USI, see [`usi`].
+#![no_std]
+
+#[cfg(test)]
+extern crate std;
+
+#[cfg(all(
+    target_arch = "avr",
+    not(any(
+        feature = "atmega48p",
+        feature = "atmega168",
+        feature = "atmega328p",
+        feature = "atmega328pb",
+        feature = "atmega32u4",
+        feature = "atmega1280",
+        feature = "atmega2560",
+        feature = "attiny85",
+        feature = "attiny88",
+    ))
+))]
+compile_error!("Select the MCU with one of the Cargo features, e.g. `atmega328p`");
+
+// Pairwise, as cfg can't count features
+macro_rules! single_mcu {
+    ($first:literal $(, $rest:literal)*) => {
+        $(
+            #[cfg(all(target_arch = "avr", feature = $first, feature = $rest))]
+            compile_error!(concat!(
+                "Features `", $first, "` and `", $rest, "` select two MCUs, enable only one ",
+                "(`--no-default-features` drops the default `atmega328p`)"
+            ));
+        )*
+        single_mcu!($($rest),*);
+    };
+    () => {};
+}
+
+single_mcu!(
+    "atmega48p",
+    "atmega168",
+    "atmega328p",
+    "atmega328pb",
+    "atmega32u4",
+    "atmega1280",
+    "atmega2560",
+    "attiny85",
+    "attiny88"
+);
+
+pub mod async_slave;
+pub mod bus_monitor;
+#[cfg(test)]
+mod bus_sim;
+pub mod handler;
+pub mod i2c_slave;
+pub mod irq_slave;
+pub mod register_map;
+pub mod rx_queue;
+pub mod snapshot;
+pub mod twi;
+pub mod twi_interrupt;
+#[cfg(test)]
+mod twi_mock;
+pub mod usi;
+
+pub use i2c_slave::{
+    Direction, End, I2CSlaveError, I2cSlave, ReadOverflow, Summary, Transfer, WriteOverflow,
+};
+pub use twi::Twi;
+pub use twi_interrupt::{TwiBinding, TwiInterrupt};
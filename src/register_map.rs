@@ -0,0 +1,235 @@
+//! Register file device model.
+//!
+//! The first byte of every master write selects a register, the following
+//! bytes are stored to consecutive registers. Reads return registers from the
+//! current pointer on. The pointer auto-increments after every byte and wraps
+//! at the end of the map.
+
+use crate::{
+    handler::Handler,
+    i2c_slave::{I2CSlaveError, I2cSlave, Transfer, GENERAL_CALL_RESET},
+    twi::Twi,
+};
+
+pub struct RegisterMap<const N: usize> {
+    regs: [u8; N],
+    ptr: usize,
+    changed: [bool; N],
+}
+
+impl<const N: usize> RegisterMap<N> {
+    const VALID: () = assert!(N > 0, "a register map needs at least one register");
+
+    pub const fn new(regs: [u8; N]) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+
+        Self {
+            regs,
+            ptr: 0,
+            changed: [false; N],
+        }
+    }
+
+    /// Apply a master write. A pointer past the end of the map wraps around.
+    pub fn write(&mut self, data: &[u8]) {
+        let Some((&ptr, data)) = data.split_first() else {
+            return;
+        };
+
+        self.ptr = ptr as usize % N;
+
+        for &byte in data {
+            self.regs[self.ptr] = byte;
+            self.changed[self.ptr] = true;
+            self.ptr = (self.ptr + 1) % N;
+        }
+    }
+
+    /// Fill `tx` with registers from the pointer on, without moving it.
+    pub fn read(&self, tx: &mut [u8]) -> usize {
+        for (i, byte) in tx.iter_mut().enumerate() {
+            *byte = self.regs[(self.ptr + i) % N];
+        }
+
+        tx.len()
+    }
+
+    /// Move the pointer past `count` registers read by the master.
+    pub fn advance(&mut self, count: usize) {
+        self.ptr = (self.ptr + count) % N;
+    }
+
+    /// Serve one transaction of a blocking `slave`. `rx` has to fit the
+    /// pointer and the longest write, `tx` the longest read.
+    pub fn serve<T: Twi>(
+        &mut self,
+        slave: &mut I2cSlave<T>,
+        rx: &mut [u8],
+        tx: &mut [u8],
+    ) -> Result<Transfer, I2CSlaveError> {
+        slave.serve(self, rx, tx)
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.ptr
+    }
+
+    pub fn registers(&self) -> &[u8; N] {
+        &self.regs
+    }
+
+    /// Registers updated by the application, these aren't marked as changed.
+    pub fn registers_mut(&mut self) -> &mut [u8; N] {
+        &mut self.regs
+    }
+
+    /// Register has been written by the master since the last clear
+    pub fn is_changed(&self, index: usize) -> bool {
+        self.changed[index]
+    }
+
+    /// Registers written by the master since the last clear
+    pub fn changed(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..N).filter(|&i| self.changed[i])
+    }
+
+    pub fn clear_changed(&mut self) {
+        self.changed = [false; N];
+    }
+}
+
+impl<const N: usize> Handler for RegisterMap<N> {
+    fn on_receive(&mut self, data: &[u8]) {
+        self.write(data);
+    }
+
+    /// A general call reset moves the pointer back to the first register,
+    /// other broadcasts are ignored
+    fn on_general_call(&mut self, data: &[u8]) {
+        if data.first() == Some(&GENERAL_CALL_RESET) {
+            self.ptr = 0;
+        }
+    }
+
+    fn on_request(&mut self, tx: &mut [u8]) -> usize {
+        self.read(tx)
+    }
+
+    fn on_stop(&mut self, result: &Result<Transfer, I2CSlaveError>) {
+        if let Ok(Transfer::Sent(count)) = result {
+            self.advance(*count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::RegisterMap;
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::Transfer,
+        twi_interrupt::TwiInterrupt,
+    };
+
+    #[test]
+    fn write_auto_increments_and_wraps() {
+        let mut map = RegisterMap::new([0; 4]);
+
+        map.write(&[2, 1, 2, 3]);
+
+        assert_eq!(map.registers(), &[3, 0, 1, 2]);
+        assert_eq!(map.pointer(), 1);
+        assert_eq!(map.changed().collect::<Vec<_>>(), [0, 2, 3]);
+
+        map.clear_changed();
+        assert_eq!(map.changed().count(), 0);
+    }
+
+    #[test]
+    fn pointer_only_write() {
+        let mut map = RegisterMap::new([10, 11, 12, 13]);
+
+        map.write(&[6]);
+
+        assert_eq!(map.pointer(), 2);
+        assert_eq!(map.changed().count(), 0);
+
+        let mut tx = [0; 3];
+        assert_eq!(map.read(&mut tx), 3);
+        assert_eq!(tx, [12, 13, 10]);
+
+        map.advance(3);
+        assert_eq!(map.pointer(), 1);
+    }
+
+    #[test]
+    fn serve_register_read_and_write() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 0xAA, 0xBB])
+            .stop()
+            .write(ADDR, &[0])
+            .read(ADDR, 4)
+            .stop()
+            .read(ADDR, 2)
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut map = RegisterMap::new([1, 2, 3, 4]);
+        let mut rx = [0; 5];
+        let mut tx = [0; 4];
+
+        for _ in 0..3 {
+            assert!(map.serve(&mut slave, &mut rx, &mut tx).is_ok());
+        }
+        assert!(matches!(
+            map.serve(&mut slave, &mut rx, &mut tx),
+            Ok(Transfer::Sent(2))
+        ));
+
+        assert_eq!(map.registers(), &[1, 0xAA, 0xBB, 4]);
+        assert!(map.is_changed(1));
+        assert!(!map.is_changed(0));
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [1, 0xAA, 0xBB, 4, 1, 0xAA]);
+    }
+
+    #[test]
+    fn general_call_reset_rewinds_pointer() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[2, 0xAA])
+            .stop()
+            .write(0, &[0x06, 0x55])
+            .stop()
+            .read(ADDR, 2)
+            .stop();
+        let mut slave = sim_slave(bus);
+        let mut map = RegisterMap::new([1, 2, 3, 4]);
+        let mut rx = [0; 4];
+        let mut tx = [0; 4];
+
+        slave.init(true);
+        assert!(matches!(
+            map.serve(&mut slave, &mut rx, &mut tx),
+            Ok(Transfer::Received(2))
+        ));
+        assert!(matches!(
+            map.serve(&mut slave, &mut rx, &mut tx),
+            Ok(Transfer::GeneralCall(2))
+        ));
+        assert!(matches!(
+            map.serve(&mut slave, &mut rx, &mut tx),
+            Ok(Transfer::Sent(2))
+        ));
+
+        // The broadcast didn't write any register
+        assert_eq!(map.registers(), &[1, 2, 0xAA, 4]);
+
+        let bus = finished(slave);
+        assert_eq!(bus.read, [1, 2]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Streaming receive through a lock-free queue.
+//!
+//! [`QueueSlave`] pushes every written byte from the TWI interrupt handler to
+//! an [`RxQueue`], which the main loop drains at its own pace without a
+//! critical section. The queue is single producer, single consumer: only the
+//! driver pushes and only the main loop pops.
+//!
+//! ```ignore
+//! static RX: RxQueue<64> = RxQueue::new();
+//!
+//! // main loop
+//! while let Some(byte) = RX.pop() { ... }
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{i2c_slave::I2cSlave, twi::Twi};
+
+/// Byte queue with `N` slots, a power of two up to 128. Indices are `u8`
+/// counters, the widest atomics the AVR has.
+pub struct RxQueue<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    // Written by the producer only
+    head: AtomicU8,
+    overruns: AtomicU8,
+    // Written by the consumer only
+    tail: AtomicU8,
+    seen_overruns: AtomicU8,
+}
+
+// Slots are only written by the producer while they're free and only read by
+// the consumer while they're filled
+unsafe impl<const N: usize> Sync for RxQueue<N> {}
+
+impl<const N: usize> Default for RxQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> RxQueue<N> {
+    const VALID: () = assert!(N.is_power_of_two() && N <= 128);
+
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::VALID;
+
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            head: AtomicU8::new(0),
+            overruns: AtomicU8::new(0),
+            tail: AtomicU8::new(0),
+            seen_overruns: AtomicU8::new(0),
+        }
+    }
+
+    /// Append a byte, producer side. A full queue drops it and counts an
+    /// overrun.
+    pub fn push(&self, byte: u8) -> bool {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        if head.wrapping_sub(tail) as usize == N {
+            let overruns = self.overruns.load(Ordering::Relaxed);
+            self.overruns
+                .store(overruns.wrapping_add(1), Ordering::Release);
+
+            return false;
+        }
+
+        unsafe { (*self.buf.get())[head as usize % N] = byte };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+
+        true
+    }
+
+    /// Take the oldest byte, consumer side
+    pub fn pop(&self) -> Option<u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let head = self.head.load(Ordering::Acquire);
+
+        if head == tail {
+            return None;
+        }
+
+        let byte = unsafe { (*self.buf.get())[tail as usize % N] };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+
+        Some(byte)
+    }
+
+    /// Pop into `buf` as far as it goes, consumer side
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let mut count = 0;
+
+        for slot in buf.iter_mut() {
+            let Some(byte) = self.pop() else {
+                break;
+            };
+
+            *slot = byte;
+            count += 1;
+        }
+
+        count
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        let tail = self.tail.load(Ordering::Acquire);
+
+        head.wrapping_sub(tail) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes dropped on a full queue since the last call, consumer side.
+    /// Counts wrap after 255.
+    pub fn take_overruns(&self) -> u8 {
+        let overruns = self.overruns.load(Ordering::Acquire);
+        let seen = self.seen_overruns.load(Ordering::Relaxed);
+
+        self.seen_overruns.store(overruns, Ordering::Relaxed);
+
+        overruns.wrapping_sub(seen)
+    }
+}
+
+/// Interrupt driven slave pushing written bytes to an [`RxQueue`]. Reads get
+/// an empty response, handled by the read overflow policy, e.g.
+/// [`ReadOverflow::Pad`](crate::i2c_slave::ReadOverflow::Pad).
+pub struct QueueSlave<'a, T: Twi, const N: usize> {
+    slave: I2cSlave<'a, T>,
+    queue: &'a RxQueue<N>,
+}
+
+impl<'a, T: Twi, const N: usize> QueueSlave<'a, T, N> {
+    /// Push the bytes written to `slave` into `queue`, driven by
+    /// [`QueueSlave::on_interrupt`].
+    pub fn new(slave: I2cSlave<'a, T>, queue: &'a RxQueue<N>) -> Self {
+        Self { slave, queue }
+    }
+
+    /// Arm the TWI, from now on written bytes are queued from the interrupt.
+    pub fn listen(&mut self) {
+        self.slave.arm();
+    }
+
+    /// Handle the TWI interrupt.
+    pub fn on_interrupt(&mut self) {
+        self.slave.take_interrupt();
+
+        match self.slave.status() {
+            // Data has been received; ACK has been returned
+            0x80 | 0x90 => {
+                let data = self.slave.take_byte();
+
+                self.queue.push(data);
+            }
+            // Everything else ends the transaction or starts a new one
+            _ => {
+                let _ = self.slave.step(None, &mut [], &[]);
+            }
+        }
+    }
+
+    /// release moved values
+    pub fn release(self) -> I2cSlave<'a, T> {
+        self.slave
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueueSlave, RxQueue};
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        twi_interrupt::TwiInterrupt,
+    };
+
+    #[test]
+    fn push_pop_wraps_and_counts_overruns() {
+        let queue = RxQueue::<4>::new();
+
+        for round in 0..3 {
+            for byte in 0..5 {
+                queue.push(round * 10 + byte);
+            }
+
+            let mut buf = [0; 8];
+            assert_eq!(queue.read(&mut buf), 4);
+            assert_eq!(buf[..4], [0, 1, 2, 3].map(|b| round * 10 + b));
+            assert!(queue.is_empty());
+        }
+
+        assert_eq!(queue.take_overruns(), 3);
+        assert_eq!(queue.take_overruns(), 0);
+    }
+
+    #[test]
+    fn interrupt_queues_written_bytes() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag)
+            .write(ADDR, &[1, 2, 3])
+            .stop()
+            .write(ADDR, &[4, 5, 6])
+            .stop();
+        let queue = RxQueue::<4>::new();
+
+        let mut slave = QueueSlave::new(sim_slave(bus), &queue);
+        slave.listen();
+
+        let mut drained = std::vec::Vec::new();
+        while flag.is_pending() {
+            slave.on_interrupt();
+
+            // Main loop only catches up after the first two bytes
+            if queue.len() == 2 && drained.is_empty() {
+                drained.extend(core::iter::from_fn(|| queue.pop()));
+            }
+        }
+        drained.extend(core::iter::from_fn(|| queue.pop()));
+
+        assert_eq!(drained, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(queue.take_overruns(), 0);
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.acks, [true; 6]);
+    }
+}
@@ -0,0 +1,300 @@
+//! Double-buffered response data.
+//!
+//! [`TxSnapshot`] keeps two copies of the response. The application composes
+//! the next one in the back buffer and publishes it by flipping an index, while
+//! the master reads the front buffer. A read latches the front buffer at the
+//! SLA+R and keeps it until the transaction ends, so the master always gets one
+//! coherent snapshot and multi-byte values never tear.
+//!
+//! The interrupt driven slave answers reads from it after
+//! [`IrqSlave::respond_from`](crate::irq_slave::IrqSlave::respond_from), the
+//! polling one with [`I2cSlave::try_respond_snapshot`].
+//!
+//! ```ignore
+//! static DATA: TxSnapshot<4> = TxSnapshot::new();
+//!
+//! slave.respond_from(&DATA);
+//!
+//! // main loop
+//! DATA.try_publish(&temperature.to_le_bytes());
+//! ```
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    i2c_slave::{I2CSlaveError, I2cSlave, Summary},
+    twi::Twi,
+};
+
+const NOT_LATCHED: u8 = 0xFF;
+
+struct Slot<const N: usize> {
+    data: [u8; N],
+    len: usize,
+}
+
+/// Response of up to `N` bytes, published by the application and latched by
+/// the driver. Only one side may publish and only one side may read. Either
+/// side may interrupt the other: the TWI interrupt handler reading in the
+/// middle of a publish from the main loop is what the double buffer is for,
+/// and a timer interrupt may as well publish while the main loop reads.
+pub struct TxSnapshot<const N: usize> {
+    slots: UnsafeCell<[Slot<N>; 2]>,
+    // Written by the publisher only
+    front: AtomicU8,
+    // Written by the reader only
+    latched: AtomicU8,
+}
+
+// The publisher only writes the back slot while it isn't latched, the reader
+// only latches the front slot
+unsafe impl<const N: usize> Sync for TxSnapshot<N> {}
+
+impl<const N: usize> Default for TxSnapshot<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> TxSnapshot<N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: UnsafeCell::new([
+                Slot {
+                    data: [0; N],
+                    len: 0,
+                },
+                Slot {
+                    data: [0; N],
+                    len: 0,
+                },
+            ]),
+            front: AtomicU8::new(0),
+            latched: AtomicU8::new(NOT_LATCHED),
+        }
+    }
+
+    /// Compose the next snapshot in place and publish it. `compose` gets the
+    /// back buffer and returns the number of bytes to send.
+    ///
+    /// Returns `false` without calling `compose` while the master still reads
+    /// the back buffer, published before the one in front. Retry after the
+    /// read has ended.
+    pub fn try_publish_with<F>(&self, compose: F) -> bool
+    where
+        F: FnOnce(&mut [u8; N]) -> usize,
+    {
+        let back = self.front.load(Ordering::Relaxed) ^ 1;
+
+        // The reader can only latch the front buffer from now on
+        if self.latched.load(Ordering::Acquire) == back {
+            return false;
+        }
+
+        let slot = unsafe { &mut (*self.slots.get())[back as usize] };
+        slot.len = compose(&mut slot.data).min(N);
+
+        self.front.store(back, Ordering::Release);
+
+        true
+    }
+
+    /// Publish `data`, truncated to `N` bytes, see
+    /// [`TxSnapshot::try_publish_with`].
+    pub fn try_publish(&self, data: &[u8]) -> bool {
+        self.try_publish_with(|buf| {
+            let len = data.len().min(N);
+            buf[..len].copy_from_slice(&data[..len]);
+
+            len
+        })
+    }
+
+    /// Keep the front buffer for the read that just started. Nothing has been
+    /// sent yet, so a buffer still latched by a read that never ended is let
+    /// go.
+    pub(crate) fn latch(&self) {
+        let front = self.front.load(Ordering::Acquire);
+        self.latched.store(front, Ordering::Release);
+    }
+
+    pub(crate) fn unlatch(&self) {
+        self.latched.store(NOT_LATCHED, Ordering::Release);
+    }
+
+    /// Latched snapshot, empty outside of a read
+    pub(crate) fn latched(&self) -> &[u8] {
+        match self.latched.load(Ordering::Relaxed) {
+            NOT_LATCHED => &[],
+            index => {
+                let slot = unsafe { &(*self.slots.get())[index as usize] };
+
+                &slot.data[..slot.len]
+            }
+        }
+    }
+}
+
+impl<'a, T: Twi> I2cSlave<'a, T> {
+    /// [`I2cSlave::try_respond`] with the snapshot published last when the
+    /// master's SLA+R arrives. The snapshot is kept until the read ends, even
+    /// if a newer one gets published meanwhile.
+    pub fn try_respond_snapshot<const N: usize>(
+        &mut self,
+        snapshot: &TxSnapshot<N>,
+    ) -> nb::Result<Summary, I2CSlaveError> {
+        // Armed first, so the SLA+R can't slip past the latch
+        self.arm_once();
+
+        // Own SLA+R has been received
+        if self.status() == 0xA8 {
+            snapshot.latch();
+        }
+
+        let result = self.try_respond(snapshot.latched());
+
+        if !matches!(result, Err(nb::Error::WouldBlock)) {
+            snapshot.unlatch();
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::TxSnapshot;
+    use crate::{
+        bus_sim::{finished, sim_slave, SimBus, ADDR},
+        i2c_slave::Transfer,
+        irq_slave::IrqSlave,
+        twi_interrupt::TwiInterrupt,
+    };
+
+    #[test]
+    fn publish_skips_latched_buffer() {
+        let snapshot = TxSnapshot::<2>::new();
+
+        assert!(snapshot.try_publish(&[1, 2, 3]));
+        snapshot.latch();
+        assert_eq!(snapshot.latched(), [1, 2]);
+
+        // Back buffer is free once, then it's the latched one
+        assert!(snapshot.try_publish_with(|buf| {
+            *buf = [3, 4];
+            2
+        }));
+        assert!(!snapshot.try_publish(&[5, 6]));
+        assert_eq!(snapshot.latched(), [1, 2]);
+
+        snapshot.unlatch();
+        assert!(snapshot.try_publish(&[5, 6]));
+        snapshot.latch();
+        assert_eq!(snapshot.latched(), [5, 6]);
+    }
+
+    #[test]
+    fn read_gets_one_snapshot() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 4).stop().read(ADDR, 4).stop();
+        let snapshot = TxSnapshot::<4>::new();
+        assert!(snapshot.try_publish(&[1; 4]));
+
+        let mut slave = sim_slave(bus);
+
+        // Application publishes a new value on every pass of the main loop
+        let mut published = Vec::new();
+        let mut value = 1;
+        for _ in 0..2 {
+            let summary = loop {
+                match slave.try_respond_snapshot(&snapshot) {
+                    Err(nb::Error::WouldBlock) => {
+                        value += 1;
+                        if snapshot.try_publish(&[value; 4]) {
+                            published.push(value);
+                        }
+                    }
+                    result => break result,
+                }
+            };
+
+            assert!(matches!(summary, Ok(summary) if summary.count == 4));
+        }
+
+        let bus = finished(slave);
+        for read in bus.read.chunks(4) {
+            assert!(read.iter().all(|&byte| byte == read[0]));
+            assert!(read[0] == 1 || published.contains(&read[0]));
+        }
+        // Publishing was refused while the back buffer was being read
+        assert!(published.len() < usize::from(value) - 1);
+    }
+
+    #[test]
+    fn interrupt_reads_one_snapshot() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 4).stop().read(ADDR, 4).stop();
+        let snapshot = TxSnapshot::<4>::new();
+        assert!(snapshot.try_publish(&[1; 4]));
+
+        let mut slave = IrqSlave::<_, 4>::new(sim_slave(bus));
+        slave.set_response(&[0xEE; 4]);
+        slave.respond_from(&snapshot);
+        slave.listen();
+
+        // Main loop publishes between interrupts
+        let mut value = 1;
+        let mut sent = 0;
+        while flag.is_pending() {
+            slave.on_interrupt();
+
+            if let Some(Ok(Transfer::Sent(count))) = slave.take_event() {
+                sent += count;
+            }
+
+            value += 1;
+            snapshot.try_publish(&[value; 4]);
+        }
+
+        assert_eq!(sent, 8);
+
+        let bus = finished(slave.release());
+        for read in bus.read.chunks(4) {
+            assert!(read.iter().all(|&byte| byte == read[0]));
+            assert_ne!(read[0], 0xEE);
+        }
+        // Second read got a snapshot published during the first
+        assert!(bus.read[4] > bus.read[0]);
+    }
+
+    #[test]
+    fn recovery_releases_latched_snapshot() {
+        let flag = TwiInterrupt::new();
+        let bus = SimBus::new(&flag).read(ADDR, 4).stop().read(ADDR, 4).stop();
+        let snapshot = TxSnapshot::<4>::new();
+        assert!(snapshot.try_publish(&[1; 4]));
+
+        let mut slave = IrqSlave::<_, 4>::new(sim_slave(bus));
+        slave.respond_from(&snapshot);
+        slave.listen();
+
+        // Bus is recovered in the middle of the first read
+        slave.on_interrupt();
+        assert!(snapshot.try_publish(&[2; 4]));
+        slave.recover_bus();
+
+        // Buffer of the aborted read is free again
+        assert!(snapshot.try_publish(&[3; 4]));
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+
+        let bus = finished(slave.release());
+        assert_eq!(bus.read, [1, 0xFF, 0xFF, 0xFF, 3, 3, 3, 3]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Register level access to the TWI peripheral.
+//!
+//! [`I2cSlave`](crate::i2c_slave::I2cSlave) only talks to the hardware through
+//! the [`Twi`] trait, so the slave state machine can be driven by a mock on the
+//! host as well as by the real TWI of the MCU selected with a Cargo feature.
+
+/// TWI Interrupt Flag
+pub const TWINT: u8 = 1 << 7;
+/// TWI Enable Acknowledge Bit
+pub const TWEA: u8 = 1 << 6;
+/// TWI START Condition Bit
+pub const TWSTA: u8 = 1 << 5;
+/// TWI STOP Condition Bit
+pub const TWSTO: u8 = 1 << 4;
+/// TWI Enable Bit
+pub const TWEN: u8 = 1 << 2;
+/// TWI Interrupt Enable
+pub const TWIE: u8 = 1 << 0;
+
+/// TWI General Call Recognition Enable Bit (TWAR)
+pub const TWGCE: u8 = 1 << 0;
+
+/// Access to the TWI registers used by the slave.
+pub trait Twi {
+    /// Status code from TWSR with the prescaler bits cleared.
+    fn status(&mut self) -> u8;
+
+    /// Read TWDR.
+    fn read_data(&mut self) -> u8;
+
+    /// Write TWDR.
+    fn write_data(&mut self, data: u8);
+
+    /// Write raw TWAR value: slave address in bits 7..1, TWGCE in bit 0.
+    fn write_address(&mut self, twar: u8);
+
+    /// Write raw TWAMR value: address bits ignored on match in bits 7..1.
+    fn write_address_mask(&mut self, twamr: u8);
+
+    /// Write raw TWCR value built from the `TW*` bit constants.
+    fn write_control(&mut self, twcr: u8);
+
+    /// Reset TWCR, disabling the TWI.
+    fn reset_control(&mut self);
+
+    /// SDA line is high.
+    fn sda_high(&mut self) -> bool;
+
+    /// SCL line is high.
+    fn scl_high(&mut self) -> bool;
+}
+
+#[cfg(all(target_arch = "avr", not(feature = "attiny85")))]
+pub use self::avr::{AvrTwi, Instance, Scl, Sda};
+#[cfg(all(target_arch = "avr", feature = "atmega328pb"))]
+pub use self::avr::{Scl1, Sda1, TWI0, TWI1};
+
+// The attiny85 has a USI instead, see `usi`
+#[cfg(all(target_arch = "avr", not(feature = "attiny85")))]
+mod avr {
+    #[cfg(any(
+        feature = "atmega48p",
+        feature = "atmega168",
+        feature = "atmega328p",
+        feature = "atmega328pb",
+        feature = "atmega32u4",
+        feature = "atmega1280",
+        feature = "atmega2560",
+    ))]
+    use atmega_hal as hal;
+    #[cfg(feature = "attiny88")]
+    use attiny_hal as hal;
+
+    use hal::port::{
+        mode::{Floating, Input},
+        Pin,
+    };
+
+    #[cfg(feature = "atmega1280")]
+    use avr_device::atmega1280::TWI;
+    #[cfg(feature = "atmega168")]
+    use avr_device::atmega168::TWI;
+    #[cfg(feature = "atmega2560")]
+    use avr_device::atmega2560::TWI;
+    #[cfg(feature = "atmega328p")]
+    use avr_device::atmega328p::TWI;
+    #[cfg(feature = "atmega328pb")]
+    use avr_device::atmega328pb::TWI0 as TWI;
+    #[cfg(feature = "atmega32u4")]
+    use avr_device::atmega32u4::TWI;
+    #[cfg(feature = "atmega48p")]
+    use avr_device::atmega48p::TWI;
+    #[cfg(feature = "attiny88")]
+    use avr_device::attiny88::TWI;
+
+    #[cfg(feature = "atmega328pb")]
+    pub use avr_device::atmega328pb::{TWI0, TWI1};
+
+    use super::{Twi, TWIE, TWINT};
+
+    /// SDA pin of the selected MCU
+    #[cfg(any(
+        feature = "atmega48p",
+        feature = "atmega168",
+        feature = "atmega328p",
+        feature = "atmega328pb",
+        feature = "attiny88",
+    ))]
+    pub type Sda = hal::port::PC4;
+    /// SCL pin of the selected MCU
+    #[cfg(any(
+        feature = "atmega48p",
+        feature = "atmega168",
+        feature = "atmega328p",
+        feature = "atmega328pb",
+        feature = "attiny88",
+    ))]
+    pub type Scl = hal::port::PC5;
+
+    /// SDA pin of the selected MCU
+    #[cfg(any(feature = "atmega32u4", feature = "atmega1280", feature = "atmega2560",))]
+    pub type Sda = hal::port::PD1;
+    /// SCL pin of the selected MCU
+    #[cfg(any(feature = "atmega32u4", feature = "atmega1280", feature = "atmega2560",))]
+    pub type Scl = hal::port::PD0;
+
+    /// SDA pin of TWI1
+    #[cfg(feature = "atmega328pb")]
+    pub type Sda1 = hal::port::PE0;
+    /// SCL pin of TWI1
+    #[cfg(feature = "atmega328pb")]
+    pub type Scl1 = hal::port::PE1;
+
+    /// TWI peripheral and the pins it drives
+    pub trait Instance {
+        type Sda;
+        type Scl;
+    }
+
+    /// TWI of the MCU selected by the Cargo feature together with its SDA/SCL
+    /// pins. MCUs with several TWIs default to the first one, e.g. TWI0 on the
+    /// atmega328pb, and take `AvrTwi<TWI1>` for the second.
+    pub struct AvrTwi<I: Instance = TWI> {
+        twi: I,
+        sda: Pin<Input<Floating>, I::Sda>,
+        scl: Pin<Input<Floating>, I::Scl>,
+    }
+
+    impl<I: Instance> AvrTwi<I> {
+        pub fn new(
+            twi: I,
+            sda: Pin<Input<Floating>, I::Sda>,
+            scl: Pin<Input<Floating>, I::Scl>,
+        ) -> Self {
+            Self { twi, sda, scl }
+        }
+
+        /// release moved values
+        pub fn release(
+            self,
+        ) -> (
+            I,
+            Pin<Input<Floating>, I::Sda>,
+            Pin<Input<Floating>, I::Scl>,
+        ) {
+            (self.twi, self.sda, self.scl)
+        }
+    }
+
+    // Every TWI has the same registers, but each peripheral has its own type
+    macro_rules! impl_twi {
+        ($twi:ident, $sda:ty, $scl:ty) => {
+            impl Instance for $twi {
+                type Sda = $sda;
+                type Scl = $scl;
+            }
+
+            impl AvrTwi<$twi> {
+                /// Disable the TWI interrupt without clearing TWINT, for an
+                /// interrupt handler that leaves the pending event to the
+                /// driver. It's enabled again by the driver's next TWCR write.
+                ///
+                /// # Safety
+                ///
+                /// Steals the TWI registers, must only be called from the TWI
+                /// interrupt handler while the driver is waiting for it.
+                pub unsafe fn mask_interrupt() {
+                    let twi = &*$twi::ptr();
+                    let twcr = twi.twcr.read().bits() & !(TWINT | TWIE);
+
+                    twi.twcr.write(|w| w.bits(twcr));
+                }
+            }
+
+            impl Twi for AvrTwi<$twi> {
+                fn status(&mut self) -> u8 {
+                    // Masking the prescaler bits according to datasheet to
+                    // read status codes correctly
+                    self.twi.twsr.read().bits() & 0xF8
+                }
+
+                fn read_data(&mut self) -> u8 {
+                    self.twi.twdr.read().bits()
+                }
+
+                fn write_data(&mut self, data: u8) {
+                    self.twi.twdr.write(|w| w.bits(data));
+                }
+
+                fn write_address(&mut self, twar: u8) {
+                    self.twi.twar.write(|w| unsafe { w.bits(twar) });
+                }
+
+                fn write_address_mask(&mut self, twamr: u8) {
+                    self.twi.twamr.write(|w| unsafe { w.bits(twamr) });
+                }
+
+                fn write_control(&mut self, twcr: u8) {
+                    self.twi.twcr.write(|w| unsafe { w.bits(twcr) });
+                }
+
+                fn reset_control(&mut self) {
+                    self.twi.twcr.reset();
+                }
+
+                fn sda_high(&mut self) -> bool {
+                    self.sda.is_high()
+                }
+
+                fn scl_high(&mut self) -> bool {
+                    self.scl.is_high()
+                }
+            }
+        };
+    }
+
+    impl_twi!(TWI, Sda, Scl);
+    #[cfg(feature = "atmega328pb")]
+    impl_twi!(TWI1, Sda1, Scl1);
+}
@@ -0,0 +1,294 @@
+//! Binding of the TWI interrupt to the driver.
+//!
+//! [`bind_twi_interrupt!`](crate::bind_twi_interrupt) declares a
+//! [`TwiInterrupt`] static and installs the TWI interrupt handler signalling
+//! it. A driver takes the interrupt with [`TwiInterrupt::bind`], which only
+//! succeeds once, so two drivers can't wait for the same TWI.
+//!
+//! ```ignore
+//! bind_twi_interrupt!(TWI_INTERRUPT);
+//!
+//! let mut slave = I2cSlave::new(twi, 0x26, TWI_INTERRUPT.bind().unwrap());
+//! ```
+//!
+//! On the atmega328pb each TWI has its own vector, named in front of the
+//! static:
+//!
+//! ```ignore
+//! bind_twi_interrupt!(TWI0 => TWI0_INTERRUPT);
+//! bind_twi_interrupt!(TWI1 => TWI1_INTERRUPT);
+//! ```
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[cfg(target_arch = "avr")]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    avr_device::interrupt::free(|_| f())
+}
+
+// Host tests don't share the driver between threads
+#[cfg(not(target_arch = "avr"))]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    f()
+}
+
+/// Declare the static `$name: TwiInterrupt` and install the TWI interrupt
+/// handler signalling it.
+///
+/// Without a handler the interrupt is masked until the driver clears TWINT,
+/// as the blocking and non-blocking APIs expect. With `$handler` it's called
+/// after signalling instead and has to service the TWI, e.g. with
+/// [`IrqSlave::on_interrupt`](crate::irq_slave::IrqSlave::on_interrupt).
+///
+/// `$vector => $name` binds the interrupt of the TWI peripheral `$vector` on
+/// MCUs with several TWIs, `TWI0` or `TWI1` on the atmega328pb. Otherwise
+/// it's the first TWI.
+///
+/// The calling crate needs `avr-device` as a dependency and
+/// `#![feature(abi_avr_interrupt)]` for the handler.
+#[macro_export]
+macro_rules! bind_twi_interrupt {
+    ($vector:ident => $name:ident) => {
+        $crate::bind_twi_interrupt!($vector => $name, || unsafe {
+            <$crate::twi::AvrTwi<$crate::twi::$vector>>::mask_interrupt()
+        });
+    };
+    ($vector:ident => $name:ident, $handler:expr) => {
+        static $name: $crate::twi_interrupt::TwiInterrupt =
+            $crate::twi_interrupt::TwiInterrupt::new();
+
+        $crate::__twi_vector! {
+            fn $vector() {
+                $name.signal();
+                ($handler)();
+            }
+        }
+    };
+    ($name:ident) => {
+        $crate::bind_twi_interrupt!($name, || unsafe { <$crate::twi::AvrTwi>::mask_interrupt() });
+    };
+    ($name:ident, $handler:expr) => {
+        $crate::__twi_default_vector!($name, $handler);
+    };
+}
+
+// Vector of the first TWI of the MCU selected with a Cargo feature
+
+#[cfg(not(feature = "atmega328pb"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_default_vector {
+    ($name:ident, $handler:expr) => {
+        $crate::bind_twi_interrupt!(TWI => $name, $handler);
+    };
+}
+
+#[cfg(feature = "atmega328pb")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_default_vector {
+    ($name:ident, $handler:expr) => {
+        $crate::bind_twi_interrupt!(TWI0 => $name, $handler);
+    };
+}
+
+// Vector attribute of the MCU selected with a Cargo feature
+
+#[cfg(feature = "atmega48p")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega48p)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega168")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega168)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega328p")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega328p)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega328pb")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega328pb)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega32u4")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega32u4)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega1280")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega1280)]
+        $handler
+    };
+}
+
+#[cfg(feature = "atmega2560")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(atmega2560)]
+        $handler
+    };
+}
+
+#[cfg(feature = "attiny88")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __twi_vector {
+    ($handler:item) => {
+        #[avr_device::interrupt(attiny88)]
+        $handler
+    };
+}
+
+/// TWI interrupt flag shared between the interrupt handler and one driver
+pub struct TwiInterrupt {
+    pending: AtomicBool,
+    bound: AtomicBool,
+    // Written by the interrupt handler only
+    missed: AtomicU8,
+    // Written by the driver only
+    seen_missed: AtomicU8,
+}
+
+impl Default for TwiInterrupt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TwiInterrupt {
+    pub const fn new() -> Self {
+        Self {
+            pending: AtomicBool::new(false),
+            bound: AtomicBool::new(false),
+            missed: AtomicU8::new(0),
+            seen_missed: AtomicU8::new(0),
+        }
+    }
+
+    /// Report an interrupt, called from the TWI interrupt handler. An
+    /// interrupt the driver hasn't taken yet counts as missed.
+    pub fn signal(&self) {
+        if self.pending.load(Ordering::SeqCst) {
+            let missed = self.missed.load(Ordering::Relaxed);
+            self.missed.store(missed.wrapping_add(1), Ordering::Release);
+        }
+
+        self.pending.store(true, Ordering::SeqCst);
+    }
+
+    /// An interrupt is waiting for the driver
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Hand the interrupt to a driver, `None` if it's bound already
+    pub fn bind(&self) -> Option<TwiBinding<'_>> {
+        free(|| {
+            if self.bound.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            self.bound.store(true, Ordering::SeqCst);
+
+            Some(TwiBinding { interrupt: self })
+        })
+    }
+}
+
+/// Exclusive access of a driver to a [`TwiInterrupt`]
+pub struct TwiBinding<'a> {
+    interrupt: &'a TwiInterrupt,
+}
+
+impl TwiBinding<'_> {
+    pub(crate) fn is_pending(&self) -> bool {
+        self.interrupt.is_pending()
+    }
+
+    /// Consume the pending interrupt
+    pub(crate) fn take(&self) -> bool {
+        if !self.is_pending() {
+            return false;
+        }
+
+        self.interrupt.pending.store(false, Ordering::SeqCst);
+
+        true
+    }
+
+    /// Missed interrupts since the last call. Counts wrap after 255.
+    pub(crate) fn take_missed(&self) -> u8 {
+        let missed = self.interrupt.missed.load(Ordering::Acquire);
+        let seen = self.interrupt.seen_missed.load(Ordering::Relaxed);
+
+        self.interrupt.seen_missed.store(missed, Ordering::Relaxed);
+
+        missed.wrapping_sub(seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TwiInterrupt;
+
+    #[test]
+    fn binds_once() {
+        let interrupt = TwiInterrupt::new();
+
+        assert!(interrupt.bind().is_some());
+        assert!(interrupt.bind().is_none());
+    }
+
+    #[test]
+    fn counts_missed_interrupts() {
+        let interrupt = TwiInterrupt::new();
+        let binding = interrupt.bind().unwrap();
+
+        interrupt.signal();
+        assert!(binding.take());
+        assert!(!binding.take());
+        assert_eq!(binding.take_missed(), 0);
+
+        interrupt.signal();
+        interrupt.signal();
+        interrupt.signal();
+        assert!(binding.take());
+        assert_eq!(binding.take_missed(), 2);
+        assert_eq!(binding.take_missed(), 0);
+    }
+}
@@ -0,0 +1,876 @@
+//! I2C slave on the USI of the attiny85.
+//!
+//! The USI only shifts bits and detects START conditions, everything else is
+//! done in software. [`UsiBus`] runs that part from the USI interrupts and
+//! reports bus events with the status codes of the TWI, so [`UsiTwi`] is just
+//! another [`Twi`] for [`I2cSlave`](crate::i2c_slave::I2cSlave) and everything
+//! built on it.
+//!
+//! Like the TWI, the USI stretches SCL after every byte until the driver has
+//! handled it. Two events don't raise a USI interrupt though, and are only
+//! seen by polling [`UsiBus::poll`], e.g. from a timer interrupt:
+//!
+//! - A STOP ending a write. Without polling the write ends on the next START.
+//! - The driver enabling the interrupt again for an event that's still held,
+//!   which [`IrqSlave`](crate::irq_slave::IrqSlave) does when it releases a
+//!   read held for an untaken write or a stretch. Without polling SCL stays
+//!   held.
+//!
+//! ```ignore
+//! // Polled on the compare match of a timer set up by the application
+//! bind_usi_interrupt!(TWI_INTERRUPT, USI_BUS, || {}, TIMER0_COMPA);
+//!
+//! let usi = Attiny85Usi::new(dp.USI, sda, scl);
+//! let twi = UsiTwi::new(usi, &USI_BUS);
+//! let mut slave = I2cSlave::new(twi, 0x26, TWI_INTERRUPT.bind().unwrap());
+//! ```
+//!
+//! Bus errors aren't detected, status `0x00` is never reported.
+
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::{
+    twi::{Twi, TWEA, TWEN, TWGCE, TWIE, TWINT, TWSTO},
+    twi_interrupt::free,
+};
+
+/// USI Start Condition Interrupt Enable
+const USISIE: u8 = 1 << 7;
+/// USI Counter Overflow Interrupt Enable
+const USIOIE: u8 = 1 << 6;
+/// USI Wire Mode
+const USIWM1: u8 = 1 << 5;
+const USIWM0: u8 = 1 << 4;
+/// USI Clock Source Select: external, positive edge
+const USICS1: u8 = 1 << 3;
+
+/// USI Start Condition Interrupt Flag
+const USISIF: u8 = 1 << 7;
+/// USI Counter Overflow Interrupt Flag
+const USIOIF: u8 = 1 << 6;
+/// USI Stop Condition Flag
+const USIPF: u8 = 1 << 5;
+/// USI Data Output Collision
+const USIDC: u8 = 1 << 4;
+
+/// Two-wire mode waiting for a START, the counter doesn't hold SCL
+const START_MODE: u8 = USISIE | USIWM1 | USICS1;
+/// Two-wire mode holding SCL on counter overflow, both interrupts enabled
+const TRANSFER_MODE: u8 = USISIE | USIOIE | USIWM1 | USIWM0 | USICS1;
+/// SCL stays held while the driver handles the event
+const HOLD_MODE: u8 = USIWM1 | USIWM0 | USICS1;
+
+const CLEAR_FLAGS: u8 = USISIF | USIOIF | USIPF | USIDC;
+/// Counter values overflowing after a byte or a single (N)ACK bit, counting
+/// both SCL edges
+const BYTE: u8 = 0;
+const BIT: u8 = 0x0E;
+
+// Software side of the transfer, what the next counter overflow means
+const IDLE: u8 = 0;
+const ADDRESS: u8 = 1;
+const RECEIVE_ACK: u8 = 2;
+const RECEIVE_NACK: u8 = 3;
+const SEND: u8 = 4;
+const SEND_LAST: u8 = 5;
+const MASTER_ACK: u8 = 6;
+const MASTER_ACK_LAST: u8 = 7;
+const SLAVE_ACK: u8 = 8;
+const HELD: u8 = 9;
+
+/// Access to the USI registers and the SDA/SCL pins.
+pub trait Usi {
+    /// Read USIDR.
+    fn data(&mut self) -> u8;
+
+    /// Write USIDR.
+    fn set_data(&mut self, data: u8);
+
+    /// Read USISR.
+    fn status(&mut self) -> u8;
+
+    /// Write USISR, flags are cleared by writing one.
+    fn write_status(&mut self, usisr: u8);
+
+    /// Write USICR.
+    fn write_control(&mut self, usicr: u8);
+
+    /// Drive SDA from USIDR, otherwise it's released.
+    fn sda_output(&mut self, output: bool);
+
+    /// SDA line is high.
+    fn sda_high(&mut self) -> bool;
+
+    /// SCL line is high.
+    fn scl_high(&mut self) -> bool;
+}
+
+/// State shared between the USI interrupts and [`UsiTwi`]: the emulated TWI
+/// registers and the progress of the transfer.
+pub struct UsiBus {
+    phase: AtomicU8,
+    // TWINT is set, the driver handles `status`
+    int: AtomicBool,
+    status: AtomicU8,
+    data: AtomicU8,
+    general_call: AtomicBool,
+    // TWIE was set again while TWINT is set, the interrupt fires once more
+    resignal: AtomicBool,
+    twar: AtomicU8,
+    twamr: AtomicU8,
+    twcr: AtomicU8,
+}
+
+impl Default for UsiBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsiBus {
+    pub const fn new() -> Self {
+        Self {
+            phase: AtomicU8::new(IDLE),
+            int: AtomicBool::new(false),
+            status: AtomicU8::new(0xF8),
+            data: AtomicU8::new(0),
+            general_call: AtomicBool::new(false),
+            resignal: AtomicBool::new(false),
+            twar: AtomicU8::new(0),
+            twamr: AtomicU8::new(0),
+            twcr: AtomicU8::new(0),
+        }
+    }
+
+    fn phase(&self) -> u8 {
+        self.phase.load(Ordering::SeqCst)
+    }
+
+    fn set_phase(&self, phase: u8) {
+        self.phase.store(phase, Ordering::SeqCst);
+    }
+
+    fn control(&self) -> u8 {
+        self.twcr.load(Ordering::SeqCst)
+    }
+
+    /// Hand `status` to the driver with SCL held. Returns whether the
+    /// interrupt has to be signalled.
+    fn raise(&self, usi: &mut impl Usi, status: u8) -> bool {
+        usi.write_control(HOLD_MODE);
+
+        self.status.store(status, Ordering::SeqCst);
+        self.int.store(true, Ordering::SeqCst);
+        self.set_phase(HELD);
+
+        self.control() & TWIE != 0
+    }
+
+    /// Release both lines and wait for the next START
+    fn listen(&self, usi: &mut impl Usi) {
+        usi.sda_output(false);
+        usi.write_control(START_MODE);
+        usi.write_status(CLEAR_FLAGS | BYTE);
+
+        self.set_phase(IDLE);
+    }
+
+    /// Shift in the address following a START
+    fn begin_address(&self, usi: &mut impl Usi) {
+        usi.sda_output(false);
+
+        // START is complete once SCL is low, unless a STOP follows right away
+        while usi.scl_high() && usi.status() & USIPF == 0 {}
+
+        if usi.status() & USIPF != 0 {
+            self.listen(usi);
+            return;
+        }
+
+        usi.write_control(TRANSFER_MODE);
+        usi.write_status(CLEAR_FLAGS | BYTE);
+
+        self.set_phase(ADDRESS);
+    }
+
+    /// Send the (N)ACK of a received byte
+    fn send_ack(&self, usi: &mut impl Usi, ack: bool) {
+        usi.set_data(if ack { 0x00 } else { 0xFF });
+        usi.sda_output(true);
+        usi.write_status(USIOIF | BIT);
+
+        self.set_phase(SLAVE_ACK);
+    }
+
+    /// Address byte matches the own address, the mask or the general call
+    fn matches(&self, sla: u8) -> (bool, bool) {
+        let twar = self.twar.load(Ordering::SeqCst);
+        let ignored = self.twamr.load(Ordering::SeqCst) >> 1;
+        let addr = sla >> 1;
+
+        let general_call = addr == 0 && sla & 1 == 0 && twar & TWGCE != 0;
+        let own = (addr ^ (twar >> 1)) & !ignored == 0;
+
+        (own || general_call, general_call && !own)
+    }
+
+    /// Handle the USI start condition interrupt. Returns whether the TWI
+    /// interrupt has to be signalled.
+    pub fn on_start(&self, usi: &mut impl Usi) -> bool {
+        if self.control() & TWEN == 0 {
+            usi.write_status(CLEAR_FLAGS);
+            return false;
+        }
+
+        match self.phase() {
+            // Repeated START ends the write, SCL is held until USISIF is
+            // cleared on resume
+            RECEIVE_ACK | RECEIVE_NACK => self.raise(usi, 0xA0),
+            _ => {
+                self.begin_address(usi);
+                false
+            }
+        }
+    }
+
+    /// Handle the USI counter overflow interrupt. Returns whether the TWI
+    /// interrupt has to be signalled.
+    pub fn on_overflow(&self, usi: &mut impl Usi) -> bool {
+        match self.phase() {
+            ADDRESS => {
+                let sla = usi.data();
+                let (matched, general_call) = self.matches(sla);
+
+                if !matched || self.control() & TWEA == 0 {
+                    self.listen(usi);
+                    return false;
+                }
+
+                let status = match (sla & 1 != 0, general_call) {
+                    (true, _) => 0xA8,
+                    (false, false) => 0x60,
+                    (false, true) => 0x70,
+                };
+
+                self.data.store(sla, Ordering::SeqCst);
+                self.status.store(status, Ordering::SeqCst);
+                self.general_call.store(general_call, Ordering::SeqCst);
+                self.send_ack(usi, true);
+
+                false
+            }
+            phase @ (RECEIVE_ACK | RECEIVE_NACK) => {
+                let ack = phase == RECEIVE_ACK;
+                let status = match (self.general_call.load(Ordering::SeqCst), ack) {
+                    (false, true) => 0x80,
+                    (false, false) => 0x88,
+                    (true, true) => 0x90,
+                    (true, false) => 0x98,
+                };
+
+                self.data.store(usi.data(), Ordering::SeqCst);
+                self.status.store(status, Ordering::SeqCst);
+                self.send_ack(usi, ack);
+
+                false
+            }
+            SLAVE_ACK => {
+                usi.sda_output(false);
+
+                self.raise(usi, self.status.load(Ordering::SeqCst))
+            }
+            phase @ (SEND | SEND_LAST) => {
+                // Read the master's (N)ACK
+                usi.sda_output(false);
+                usi.set_data(0);
+                usi.write_status(USIOIF | BIT);
+
+                self.set_phase(if phase == SEND {
+                    MASTER_ACK
+                } else {
+                    MASTER_ACK_LAST
+                });
+
+                false
+            }
+            phase @ (MASTER_ACK | MASTER_ACK_LAST) => {
+                let status = if usi.data() & 1 != 0 {
+                    0xC0
+                } else if phase == MASTER_ACK_LAST {
+                    0xC8
+                } else {
+                    0xB8
+                };
+
+                self.raise(usi, status)
+            }
+            _ => {
+                self.listen(usi);
+                false
+            }
+        }
+    }
+
+    /// Report a STOP ending a write, which the USI only flags, and fire the
+    /// interrupt again for a held event the driver re-enabled it for. Returns
+    /// whether the TWI interrupt has to be signalled.
+    pub fn poll(&self, usi: &mut impl Usi) -> bool {
+        free(|| {
+            let resignal = self.resignal.load(Ordering::SeqCst);
+            self.resignal.store(false, Ordering::SeqCst);
+
+            if resignal && self.int.load(Ordering::SeqCst) {
+                return true;
+            }
+
+            let receiving = matches!(self.phase(), RECEIVE_ACK | RECEIVE_NACK);
+
+            receiving && usi.status() & USIPF != 0 && self.raise(usi, 0xA0)
+        })
+    }
+}
+
+/// [`Twi`] emulated on the USI together with the interrupts running `bus`
+pub struct UsiTwi<'a, U: Usi> {
+    usi: U,
+    bus: &'a UsiBus,
+}
+
+impl<'a, U: Usi> UsiTwi<'a, U> {
+    pub fn new(usi: U, bus: &'a UsiBus) -> Self {
+        Self { usi, bus }
+    }
+
+    /// release moved values
+    pub fn release(self) -> U {
+        self.usi
+    }
+
+    /// Continue the transfer after the driver has handled `status`
+    fn resume(&mut self, status: u8, ack: bool) {
+        let usi = &mut self.usi;
+        let bus = self.bus;
+
+        match status {
+            // Addressed as receiver, shift in the next byte
+            0x60 | 0x70 | 0x80 | 0x90 => {
+                usi.write_control(TRANSFER_MODE);
+                usi.write_status(USIOIF | BYTE);
+
+                bus.set_phase(if ack { RECEIVE_ACK } else { RECEIVE_NACK });
+            }
+            // Addressed as transmitter, shift out TWDR
+            0xA8 | 0xB8 => {
+                usi.set_data(bus.data.load(Ordering::SeqCst));
+                usi.sda_output(true);
+                usi.write_control(TRANSFER_MODE);
+                usi.write_status(USIOIF | BYTE);
+
+                bus.set_phase(if ack { SEND } else { SEND_LAST });
+            }
+            // Serve the repeated START which has been held
+            0xA0 if usi.status() & USISIF != 0 => bus.begin_address(usi),
+            _ => bus.listen(usi),
+        }
+    }
+}
+
+impl<U: Usi> Twi for UsiTwi<'_, U> {
+    fn status(&mut self) -> u8 {
+        if self.bus.int.load(Ordering::SeqCst) {
+            self.bus.status.load(Ordering::SeqCst)
+        } else {
+            0xF8
+        }
+    }
+
+    fn read_data(&mut self) -> u8 {
+        self.bus.data.load(Ordering::SeqCst)
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.bus.data.store(data, Ordering::SeqCst);
+    }
+
+    fn write_address(&mut self, twar: u8) {
+        self.bus.twar.store(twar, Ordering::SeqCst);
+    }
+
+    fn write_address_mask(&mut self, twamr: u8) {
+        self.bus.twamr.store(twamr, Ordering::SeqCst);
+    }
+
+    fn write_control(&mut self, twcr: u8) {
+        let enabled = self.bus.control() & TWEN != 0;
+        self.bus.twcr.store(twcr & !TWINT, Ordering::SeqCst);
+        self.bus.resignal.store(false, Ordering::SeqCst);
+
+        // Disabled USI releases the bus and forgets the transfer
+        if twcr & TWEN == 0 {
+            self.bus.int.store(false, Ordering::SeqCst);
+            self.usi.sda_output(false);
+            self.usi.write_control(0);
+            self.bus.set_phase(IDLE);
+            return;
+        }
+
+        if !enabled || twcr & TWSTO != 0 {
+            self.bus.int.store(false, Ordering::SeqCst);
+            self.bus.listen(&mut self.usi);
+            return;
+        }
+
+        if !self.bus.int.load(Ordering::SeqCst) {
+            return;
+        }
+
+        if twcr & TWINT != 0 {
+            self.bus.int.store(false, Ordering::SeqCst);
+
+            let status = self.bus.status.load(Ordering::SeqCst);
+            self.resume(status, twcr & TWEA != 0);
+        } else if twcr & TWIE != 0 {
+            // The TWI would interrupt again right away, the USI doesn't
+            self.bus.resignal.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn reset_control(&mut self) {
+        self.write_control(0);
+    }
+
+    fn sda_high(&mut self) -> bool {
+        self.usi.sda_high()
+    }
+
+    fn scl_high(&mut self) -> bool {
+        self.usi.scl_high()
+    }
+}
+
+/// Declare the statics `$name: TwiInterrupt` and `$bus: UsiBus` and install
+/// the USI interrupt handlers running the bus and signalling the interrupt.
+/// `$handler` is called after signalling, see
+/// [`bind_twi_interrupt!`](crate::bind_twi_interrupt). With a `$timer`
+/// vector its handler runs [`UsiBus::poll`](crate::usi::UsiBus::poll), the
+/// timer itself is set up by the application.
+///
+/// The calling crate needs `avr-device` as a dependency and
+/// `#![feature(abi_avr_interrupt)]` for the handlers.
+#[macro_export]
+macro_rules! bind_usi_interrupt {
+    ($name:ident, $bus:ident) => {
+        $crate::bind_usi_interrupt!($name, $bus, || {});
+    };
+    ($name:ident, $bus:ident, $handler:expr, $timer:ident) => {
+        $crate::bind_usi_interrupt!($name, $bus, $handler);
+
+        #[avr_device::interrupt(attiny85)]
+        fn $timer() {
+            if $bus.poll(&mut unsafe { $crate::usi::Attiny85Usi::steal() }) {
+                $name.signal();
+                ($handler)();
+            }
+        }
+    };
+    ($name:ident, $bus:ident, $handler:expr) => {
+        static $name: $crate::twi_interrupt::TwiInterrupt =
+            $crate::twi_interrupt::TwiInterrupt::new();
+        static $bus: $crate::usi::UsiBus = $crate::usi::UsiBus::new();
+
+        #[avr_device::interrupt(attiny85)]
+        fn USI_START() {
+            if $bus.on_start(&mut unsafe { $crate::usi::Attiny85Usi::steal() }) {
+                $name.signal();
+                ($handler)();
+            }
+        }
+
+        #[avr_device::interrupt(attiny85)]
+        fn USI_OVF() {
+            if $bus.on_overflow(&mut unsafe { $crate::usi::Attiny85Usi::steal() }) {
+                $name.signal();
+                ($handler)();
+            }
+        }
+    };
+}
+
+#[cfg(all(target_arch = "avr", feature = "attiny85"))]
+pub use self::attiny85::Attiny85Usi;
+
+#[cfg(all(target_arch = "avr", feature = "attiny85"))]
+mod attiny85 {
+    use attiny_hal::port::{
+        mode::{Floating, Input},
+        Pin, PB0, PB2,
+    };
+    use avr_device::attiny85::{PORTB, USI};
+
+    use super::{Usi, USIWM1};
+
+    const SDA: u8 = 1 << 0;
+    const SCL: u8 = 1 << 2;
+
+    /// USI of the attiny85, SDA on PB0 and SCL on PB2. Pins and registers are
+    /// accessed through pointers, so the interrupt handlers can use a stolen
+    /// instance.
+    pub struct Attiny85Usi {
+        _private: (),
+    }
+
+    impl Attiny85Usi {
+        /// Take the USI and its pins. SCL is driven by the USI while it's in
+        /// two-wire mode, SDA only while sending.
+        pub fn new(
+            _usi: USI,
+            _sda: Pin<Input<Floating>, PB0>,
+            _scl: Pin<Input<Floating>, PB2>,
+        ) -> Self {
+            let portb = unsafe { &*PORTB::ptr() };
+
+            // Output drivers only pull low in two-wire mode, which isn't
+            // selected yet
+            portb
+                .portb
+                .modify(|r, w| unsafe { w.bits(r.bits() | SDA | SCL) });
+            portb
+                .ddrb
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(SDA | SCL)) });
+
+            Self { _private: () }
+        }
+
+        fn scl_output(&mut self, output: bool) {
+            self.portb().ddrb.modify(|r, w| unsafe {
+                if output {
+                    w.bits(r.bits() | SCL)
+                } else {
+                    w.bits(r.bits() & !SCL)
+                }
+            });
+        }
+
+        /// # Safety
+        ///
+        /// Must only be used by the USI interrupt handlers, while the driver
+        /// owns the instance returned by [`Attiny85Usi::new`].
+        pub unsafe fn steal() -> Self {
+            Self { _private: () }
+        }
+
+        fn usi(&self) -> &avr_device::attiny85::usi::RegisterBlock {
+            unsafe { &*USI::ptr() }
+        }
+
+        fn portb(&self) -> &avr_device::attiny85::portb::RegisterBlock {
+            unsafe { &*PORTB::ptr() }
+        }
+    }
+
+    impl Usi for Attiny85Usi {
+        fn data(&mut self) -> u8 {
+            self.usi().usidr.read().bits()
+        }
+
+        fn set_data(&mut self, data: u8) {
+            self.usi().usidr.write(|w| unsafe { w.bits(data) });
+        }
+
+        fn status(&mut self) -> u8 {
+            self.usi().usisr.read().bits()
+        }
+
+        fn write_status(&mut self, usisr: u8) {
+            self.usi().usisr.write(|w| unsafe { w.bits(usisr) });
+        }
+
+        fn write_control(&mut self, usicr: u8) {
+            // Outside of two-wire mode the SCL output would drive high
+            let two_wire = usicr & USIWM1 != 0;
+
+            if !two_wire {
+                self.scl_output(false);
+            }
+
+            self.usi().usicr.write(|w| unsafe { w.bits(usicr) });
+
+            if two_wire {
+                self.scl_output(true);
+            }
+        }
+
+        fn sda_output(&mut self, output: bool) {
+            self.portb().ddrb.modify(|r, w| unsafe {
+                if output {
+                    w.bits(r.bits() | SDA)
+                } else {
+                    w.bits(r.bits() & !SDA)
+                }
+            });
+        }
+
+        fn sda_high(&mut self) -> bool {
+            self.portb().pinb.read().bits() & SDA != 0
+        }
+
+        fn scl_high(&mut self) -> bool {
+            self.portb().pinb.read().bits() & SCL != 0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use std::vec::Vec;
+
+    use super::{Usi, UsiBus, UsiTwi, USIOIF, USIPF, USISIF};
+    use crate::{
+        bus_sim::ADDR,
+        i2c_slave::{I2cSlave, Transfer},
+        irq_slave::IrqSlave,
+        twi_interrupt::TwiInterrupt,
+    };
+
+    /// USI registers, with SCL low whenever the interrupts look at it
+    #[derive(Default)]
+    struct FakeUsi {
+        data: Cell<u8>,
+        flags: Cell<u8>,
+        counter: Cell<u8>,
+        sda_output: Cell<bool>,
+        /// Bytes shifted out while SDA was driven
+        sent: Cell<Vec<u8>>,
+    }
+
+    impl Usi for &FakeUsi {
+        fn data(&mut self) -> u8 {
+            self.data.get()
+        }
+
+        fn set_data(&mut self, data: u8) {
+            self.data.set(data);
+        }
+
+        fn status(&mut self) -> u8 {
+            self.flags.get() | self.counter.get()
+        }
+
+        fn write_status(&mut self, usisr: u8) {
+            self.flags.set(self.flags.get() & !(usisr & 0xF0));
+            self.counter.set(usisr & 0x0F);
+        }
+
+        fn write_control(&mut self, _usicr: u8) {}
+
+        fn sda_output(&mut self, output: bool) {
+            self.sda_output.set(output);
+        }
+
+        fn sda_high(&mut self) -> bool {
+            true
+        }
+
+        fn scl_high(&mut self) -> bool {
+            false
+        }
+    }
+
+    /// Master side of the bus, running the USI interrupts
+    struct Master<'a> {
+        usi: &'a FakeUsi,
+        bus: &'a UsiBus,
+        interrupt: &'a TwiInterrupt,
+    }
+
+    impl Master<'_> {
+        fn signal(&self, raised: bool) {
+            if raised {
+                self.interrupt.signal();
+            }
+        }
+
+        fn start(&self) {
+            self.usi.flags.set(USISIF);
+            self.signal(self.bus.on_start(&mut { self.usi }));
+        }
+
+        fn stop(&self) {
+            self.usi.flags.set(self.usi.flags.get() | USIPF);
+            self.poll();
+        }
+
+        /// Timer interrupt polling the bus
+        fn poll(&self) {
+            self.signal(self.bus.poll(&mut { self.usi }));
+        }
+
+        /// Shift a byte and its (N)ACK, returning whether the slave ACKed
+        fn write(&self, byte: u8) -> bool {
+            self.usi.data.set(byte);
+            self.signal(self.bus.on_overflow(&mut { self.usi }));
+
+            let ack = self.usi.sda_output.get() && self.usi.data.get() & 0x80 == 0;
+            self.signal(self.bus.on_overflow(&mut { self.usi }));
+
+            ack
+        }
+
+        /// Shift a byte from the slave and answer with `ack`
+        fn read(&self, ack: bool) {
+            assert!(self.usi.sda_output.get());
+
+            let mut sent = self.usi.sent.take();
+            sent.push(self.usi.data.get());
+            self.usi.sent.set(sent);
+
+            self.signal(self.bus.on_overflow(&mut { self.usi }));
+
+            self.usi.data.set(u8::from(!ack));
+            self.signal(self.bus.on_overflow(&mut { self.usi }));
+        }
+    }
+
+    #[test]
+    fn receive_over_usi() {
+        let flag = TwiInterrupt::new();
+        let bus = UsiBus::new();
+        let usi = FakeUsi::default();
+        let master = Master {
+            usi: &usi,
+            bus: &bus,
+            interrupt: &flag,
+        };
+
+        let mut slave = I2cSlave::new(UsiTwi::new(&usi, &bus), ADDR, flag.bind().unwrap());
+        slave.init(false);
+        let mut buf = [0; 4];
+
+        assert!(matches!(
+            slave.try_receive(&mut buf),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        master.start();
+        assert!(!master.write(0x10 << 1));
+        assert_eq!(usi.flags.get() & USIOIF, 0);
+
+        master.start();
+        let mut acks = Vec::new();
+        for byte in [ADDR << 1, 1, 2] {
+            acks.push(master.write(byte));
+            assert!(matches!(
+                slave.try_receive(&mut buf),
+                Err(nb::Error::WouldBlock)
+            ));
+        }
+        master.stop();
+
+        assert!(matches!(
+            slave.try_receive(&mut buf),
+            Ok(summary) if summary.count == 2
+        ));
+        assert_eq!(acks, [true; 3]);
+        assert_eq!(buf[..2], [1, 2]);
+    }
+
+    #[test]
+    fn respond_over_usi() {
+        let flag = TwiInterrupt::new();
+        let bus = UsiBus::new();
+        let usi = FakeUsi::default();
+        let master = Master {
+            usi: &usi,
+            bus: &bus,
+            interrupt: &flag,
+        };
+
+        let mut slave = I2cSlave::new(UsiTwi::new(&usi, &bus), ADDR, flag.bind().unwrap());
+        slave.init(false);
+        let response = [7, 8, 9];
+
+        assert!(matches!(
+            slave.try_respond(&response),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        master.start();
+        assert!(master.write(ADDR << 1 | 1));
+        assert!(matches!(
+            slave.try_respond(&response),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        master.read(true);
+        assert!(matches!(
+            slave.try_respond(&response),
+            Err(nb::Error::WouldBlock)
+        ));
+
+        master.read(false);
+        assert!(matches!(
+            slave.try_respond(&response),
+            Ok(summary) if summary.count == 2 && summary.short_read()
+        ));
+
+        assert_eq!(usi.sent.take(), [7, 8]);
+        assert!(!usi.sda_output.get());
+    }
+
+    /// Run the interrupt handler while it's signalled
+    fn service(flag: &TwiInterrupt, slave: &mut IrqSlave<'_, UsiTwi<'_, &FakeUsi>, 2>) {
+        while flag.is_pending() {
+            slave.on_interrupt();
+        }
+    }
+
+    #[test]
+    fn interrupt_write_then_read() {
+        let flag = TwiInterrupt::new();
+        let bus = UsiBus::new();
+        let usi = FakeUsi::default();
+        let master = Master {
+            usi: &usi,
+            bus: &bus,
+            interrupt: &flag,
+        };
+
+        let mut slave = I2cSlave::new(UsiTwi::new(&usi, &bus), ADDR, flag.bind().unwrap());
+        slave.init(false);
+        let mut slave = IrqSlave::<_, 2>::new(slave);
+        slave.listen();
+
+        master.start();
+        for byte in [ADDR << 1, 5] {
+            assert!(master.write(byte));
+            service(&flag, &mut slave);
+        }
+
+        // Repeated START, the SLA+R is held until the write is taken
+        master.start();
+        service(&flag, &mut slave);
+        assert!(master.write(ADDR << 1 | 1));
+        service(&flag, &mut slave);
+        assert!(slave.stretching());
+
+        assert!(matches!(
+            slave.take_event(),
+            Some(Ok(Transfer::Received(1)))
+        ));
+        slave.set_response(&[slave.received()[0] * 10, 60]);
+
+        // The USI doesn't interrupt again on its own, the timer picks it up
+        assert!(!flag.is_pending());
+        master.poll();
+        service(&flag, &mut slave);
+
+        master.read(true);
+        service(&flag, &mut slave);
+        master.read(false);
+        service(&flag, &mut slave);
+
+        assert!(matches!(slave.take_event(), Some(Ok(Transfer::Sent(2)))));
+        assert_eq!(usi.sent.take(), [50, 60]);
+    }
+}